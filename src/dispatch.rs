@@ -0,0 +1,158 @@
+//! # Event listeners
+//!
+//! `EventSource` works like its namesake in browsers: instead of matching on `event_type` in a
+//! loop, register a handler per event type and let a background thread call them.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use eventsource::dispatch::EventSource;
+//! use eventsource::reqwest::Client;
+//! use reqwest::Url;
+//!
+//! let client = Client::new(Url::parse("http://example.com").unwrap());
+//! let mut source = EventSource::new(client);
+//! source.on("price", |event| println!("new price: {}", event.data));
+//! source.on_message(|event| println!("message: {}", event.data));
+//! source.fallback(|event| println!("unknown event: {}", event));
+//! let handle = source.spawn();
+//! // ...
+//! handle.close();
+//! handle.join().unwrap();
+//! ```
+
+use super::event::Event;
+use super::reqwest::{Client, CloseHandle, Error, ErrorHandler};
+use std::collections::HashMap;
+use std::fmt;
+use std::thread::{self, JoinHandle};
+
+type EventHandler = Box<dyn FnMut(&Event) + Send>;
+
+/// Event type of events without an `event` field.
+const MESSAGE: &str = "message";
+
+/// Routes the events of a `Client` to handlers registered by event type.
+pub struct EventSource {
+    client: Client,
+    handlers: HashMap<String, EventHandler>,
+    fallback: Option<EventHandler>,
+    on_error: Option<ErrorHandler>,
+}
+
+impl EventSource {
+    /// Creates a dispatcher for the events of `client`.
+    ///
+    /// Configure the client before, it is not accessible afterwards.
+    pub fn new(client: Client) -> EventSource {
+        EventSource {
+            client,
+            handlers: HashMap::new(),
+            fallback: None,
+            on_error: None,
+        }
+    }
+
+    /// Sets the handler for events of type `event_type`, replacing any previous one.
+    pub fn on<F>(&mut self, event_type: &str, f: F)
+    where
+        F: FnMut(&Event) + Send + 'static,
+    {
+        self.handlers.insert(event_type.to_string(), Box::new(f));
+    }
+
+    /// Sets the handler for events without an `event` field, which have the type `message`.
+    pub fn on_message<F>(&mut self, f: F)
+    where
+        F: FnMut(&Event) + Send + 'static,
+    {
+        self.on(MESSAGE, f);
+    }
+
+    /// Sets the handler for events of types without a handler of their own.
+    ///
+    /// Without one, these events are dropped.
+    pub fn fallback<F>(&mut self, f: F)
+    where
+        F: FnMut(&Event) + Send + 'static,
+    {
+        self.fallback = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called for every error the client returns.
+    ///
+    /// The client keeps running after retryable errors, see `Error::is_fatal`.
+    pub fn on_error<F>(&mut self, f: F)
+    where
+        F: FnMut(&Error) + Send + 'static,
+    {
+        self.on_error = Some(Box::new(f));
+    }
+
+    /// Returns a handle which can close the client, e.g. from within a handler.
+    pub fn close_handle(&mut self) -> CloseHandle {
+        self.client.close_handle()
+    }
+
+    /// Dispatches events on the current thread until the client stops.
+    pub fn run(mut self) {
+        while let Some(result) = self.client.next() {
+            match result {
+                Ok(event) => self.dispatch(&event),
+                Err(err) => {
+                    if let Some(ref mut on_error) = self.on_error {
+                        on_error(&err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Dispatches events on a new thread until the client stops or is closed.
+    pub fn spawn(mut self) -> EventSourceHandle {
+        let close = self.client.close_handle();
+        let thread = thread::spawn(move || self.run());
+        EventSourceHandle { close, thread }
+    }
+
+    fn dispatch(&mut self, event: &Event) {
+        let event_type = event.event_type.as_deref().unwrap_or(MESSAGE);
+        if let Some(handler) = self.handlers.get_mut(event_type) {
+            handler(event);
+        } else if let Some(ref mut fallback) = self.fallback {
+            fallback(event);
+        }
+    }
+}
+
+/// A handle to an `EventSource` running on its own thread.
+///
+/// Created by `EventSource::spawn()`. Dropping the handle does not stop the thread.
+pub struct EventSourceHandle {
+    close: CloseHandle,
+    thread: JoinHandle<()>,
+}
+
+impl EventSourceHandle {
+    /// Closes the client, which ends the thread.
+    ///
+    /// A handler which is running at that moment finishes first.
+    pub fn close(&self) {
+        self.close.close();
+    }
+
+    /// Waits for the thread to finish.
+    ///
+    /// Returns an error if a handler panicked.
+    pub fn join(self) -> thread::Result<()> {
+        self.thread.join()
+    }
+}
+
+impl fmt::Debug for EventSourceHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventSourceHandle")
+            .field("closed", &self.close.is_closed())
+            .finish()
+    }
+}
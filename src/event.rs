@@ -1,8 +1,13 @@
+use std::error;
 use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::ops::Range;
+use std::str;
 use std::time::Duration;
 
 /// A single Server-Sent Event.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Event {
     /// Corresponds to the `id` field.
     pub id: Option<String>,
@@ -10,6 +15,16 @@ pub struct Event {
     pub event_type: Option<String>,
     /// All `data` fields concatenated by newlines.
     pub data: String,
+    /// Corresponds to the `retry` field.
+    ///
+    /// The reconnection time applies to the whole stream, not only to this event. It is kept here
+    /// so that the stream can be written back out unchanged.
+    pub retry: Option<Duration>,
+    /// Fields other than `event`, `data`, `id` and `retry` as `(field, value)` pairs, in stream
+    /// order.
+    ///
+    /// These are ignored by default. Set `ParserOptions::keep_unknown_fields` to fill them in.
+    pub extra_fields: Vec<(String, String)>,
 }
 
 /// Possible results from parsing a single event-stream line.
@@ -21,6 +36,10 @@ pub enum ParseResult {
     Dispatch,
     /// Set retry time.
     SetRetry(Duration),
+    /// The line is a comment. Holds the text after the colon and an optional space.
+    ///
+    /// Servers often send comments to keep idle connections alive.
+    Comment(String),
 }
 
 /// Parse a single line of an event-stream.
@@ -39,6 +58,7 @@ pub enum ParseResult {
 /// let mut event = Event::new();
 /// assert_eq!(parse_event_line("id: 42", &mut event), ParseResult::Next);
 /// assert_eq!(parse_event_line("data: foobar", &mut event), ParseResult::Next);
+/// assert_eq!(parse_event_line(": hi", &mut event), ParseResult::Comment("hi".into()));
 /// assert_eq!(parse_event_line("", &mut event), ParseResult::Dispatch);
 /// // The event is finished now.
 /// assert_eq!(event.id, Some("42".into()));
@@ -48,27 +68,22 @@ pub enum ParseResult {
 /// // ...
 /// ```
 pub fn parse_event_line(line: &str, event: &mut Event) -> ParseResult {
-    let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
-    if line == "" {
+    let line = line.trim_end_matches(&['\r', '\n'][..]);
+    if line.is_empty() {
         ParseResult::Dispatch
     } else {
-        let (field, value) = if let Some(pos) = line.find(':') {
-            let (f, v) = line.split_at(pos);
-            // Strip : and an optional space.
-            let v = &v[1..];
-            let v = if v.starts_with(' ') { &v[1..] } else { v };
-            (f, v)
-        } else {
-            (line, "")
-        };
-        
+        let (field, value) = split_field(line);
+
         match field {
+            "" => { return ParseResult::Comment(value.to_string()); }
             "event" => { event.event_type = Some(value.to_string()); },
             "data" => { event.data.push_str(value); event.data.push('\n'); },
             "id" => { event.id = Some(value.to_string()); }
             "retry" => {
                 if let Ok(retry) = value.parse::<u64>() {
-                    return ParseResult::SetRetry(Duration::from_millis(retry));
+                    let retry = Duration::from_millis(retry);
+                    event.retry = Some(retry);
+                    return ParseResult::SetRetry(retry);
                 }
             },
             _ => () // ignored
@@ -78,6 +93,21 @@ pub fn parse_event_line(line: &str, event: &mut Event) -> ParseResult {
     }
 }
 
+/// Splits a non-empty line into field name and value.
+///
+/// For comments, the field name is empty.
+fn split_field(line: &str) -> (&str, &str) {
+    if let Some(pos) = line.find(':') {
+        let (f, v) = line.split_at(pos);
+        // Strip : and an optional space.
+        let v = &v[1..];
+        let v = v.strip_prefix(' ').unwrap_or(v);
+        (f, v)
+    } else {
+        (line, "")
+    }
+}
+
 impl Event {
     /// Creates an empty event.
     pub fn new() -> Event {
@@ -85,14 +115,21 @@ impl Event {
             id: None,
             event_type: None,
             data: "".to_string(),
+            retry: None,
+            extra_fields: Vec::new(),
         }
     }
 
     /// Returns `true` if the event is empty.
     ///
-    /// An event is empty if it has no id or event type and its data field is empty.
+    /// An event is empty if it has no id, event type, retry or extra fields and its data field is
+    /// empty.
     pub fn is_empty(&self) -> bool {
-        self.id.is_none() && self.event_type.is_none() && self.data.is_empty()
+        self.id.is_none()
+            && self.event_type.is_none()
+            && self.data.is_empty()
+            && self.retry.is_none()
+            && self.extra_fields.is_empty()
     }
 
     /// Makes the event empty.
@@ -100,24 +137,997 @@ impl Event {
         self.id = None;
         self.event_type = None;
         self.data.clear();
+        self.retry = None;
+        self.extra_fields.clear();
+    }
+}
+
+impl Default for Event {
+    fn default() -> Event {
+        Event::new()
     }
 }
 
 impl fmt::Display for Event {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if let Some(ref id) = self.id {
-            write!(f, "id: {}\n", id)?;
+            writeln!(f, "id: {}", id)?;
         }
         if let Some(ref event_type) = self.event_type {
-            write!(f, "event: {}\n", event_type)?;
+            writeln!(f, "event: {}", event_type)?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        for (field, value) in &self.extra_fields {
+            writeln!(f, "{}: {}", field, value)?;
         }
         for line in self.data.lines() {
-            write!(f, "data: {}\n", line)?;
+            writeln!(f, "data: {}", line)?;
         }
         Ok(())
     }
 }
 
+/// Writes events in `text/event-stream` format, e.g. for implementing a server.
+///
+/// In contrast to the `Display` implementation of `Event`, the writer makes sure that the output
+/// is read back as exactly the events that were written:
+///
+///  - `id`, `event` and extra fields must not contain line breaks, which would allow injecting
+///    other fields. Such events are rejected with an `io::ErrorKind::InvalidInput` error and
+///    nothing is written. `id` must not contain NUL either, as clients ignore those.
+///  - Extra fields must not use the names `event`, `data`, `id` or `retry`, which clients would
+///    read as the corresponding fields of the event.
+///  - `data` is split on CR, LF and CRLF, like the parser does. As with `str::lines`, a single
+///    line break at the end does not start another line.
+///  - Each event is terminated by an empty line.
+///
+/// # Examples
+///
+/// ```
+/// # use eventsource::event::{Event, EventWriter};
+/// let mut writer = EventWriter::new(Vec::new());
+/// let event = Event { id: Some("1".into()), data: "a\r\nb".into(), ..Event::new() };
+/// writer.write_event(&event).unwrap();
+/// assert_eq!(writer.into_inner(), b"id: 1\ndata: a\ndata: b\n\n");
+///
+/// let event = Event { event_type: Some("x\ndata: injected".into()), ..Event::new() };
+/// assert!(EventWriter::new(Vec::new()).write_event(&event).is_err());
+/// ```
+#[derive(Debug)]
+pub struct EventWriter<W> {
+    inner: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> EventWriter<W> {
+    /// Creates a writer writing to `inner`.
+    ///
+    /// Every event is written with a single `write_all` call. Wrap `inner` in a `BufWriter` if
+    /// you want to batch multiple events.
+    pub fn new(inner: W) -> EventWriter<W> {
+        EventWriter { inner, buf: Vec::new() }
+    }
+
+    /// Writes a single event, including the terminating empty line.
+    pub fn write_event(&mut self, event: &Event) -> io::Result<()> {
+        if let Some(ref id) = event.id {
+            check_value("id", id, &['\r', '\n', '\0'])?;
+        }
+        if let Some(ref event_type) = event.event_type {
+            check_value("event", event_type, &['\r', '\n'])?;
+        }
+        for (field, value) in &event.extra_fields {
+            if field.is_empty() {
+                return Err(invalid_input("empty field name".to_string()));
+            }
+            check_value("field name", field, &['\r', '\n', ':'])?;
+            if RESERVED_FIELDS.contains(&field.as_str()) {
+                return Err(invalid_input(format!("reserved field name {:?}", field)));
+            }
+            check_value(field, value, &['\r', '\n'])?;
+        }
+
+        self.buf.clear();
+        if let Some(ref id) = event.id {
+            writeln!(self.buf, "id: {}", id)?;
+        }
+        if let Some(ref event_type) = event.event_type {
+            writeln!(self.buf, "event: {}", event_type)?;
+        }
+        if let Some(retry) = event.retry {
+            writeln!(self.buf, "retry: {}", retry.as_millis())?;
+        }
+        for (field, value) in &event.extra_fields {
+            writeln!(self.buf, "{}: {}", field, value)?;
+        }
+        for line in data_lines(&event.data) {
+            writeln!(self.buf, "data: {}", line)?;
+        }
+        self.buf.push(b'\n');
+        self.inner.write_all(&self.buf)
+    }
+
+    /// Writes a comment. Comments with multiple lines are split into multiple comments.
+    ///
+    /// Clients ignore comments, but they are useful to keep idle connections open.
+    pub fn write_comment(&mut self, text: &str) -> io::Result<()> {
+        self.buf.clear();
+        for line in data_lines(text) {
+            writeln!(self.buf, ": {}", line)?;
+        }
+        if self.buf.is_empty() {
+            self.buf.extend_from_slice(b":\n");
+        }
+        self.inner.write_all(&self.buf)
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Field names with a meaning of their own, which extra fields must not use.
+const RESERVED_FIELDS: &[&str] = &["event", "data", "id", "retry"];
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn check_value(name: &str, value: &str, forbidden: &[char]) -> io::Result<()> {
+    match value.find(forbidden) {
+        Some(pos) => Err(invalid_input(format!(
+            "{} contains forbidden character {:?} at position {}",
+            name,
+            value[pos..].chars().next().unwrap(),
+            pos
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Splits `data` on CR, LF and CRLF. A trailing line break does not start a new line.
+fn data_lines(data: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(data).filter(|d| !d.is_empty());
+    std::iter::from_fn(move || {
+        let data = rest?;
+        match data.find(&['\r', '\n'][..]) {
+            Some(pos) => {
+                let next = if data[pos..].starts_with("\r\n") { pos + 2 } else { pos + 1 };
+                rest = Some(&data[next..]).filter(|d| !d.is_empty());
+                Some(&data[..pos])
+            }
+            None => {
+                rest = None;
+                Some(data)
+            }
+        }
+    })
+}
+
+/// Items produced by an `EventStreamParser`.
+#[derive(Debug, PartialEq)]
+pub enum StreamItem {
+    /// A complete event.
+    Event(Event),
+    /// The stream asked for a new reconnection time.
+    Retry(Duration),
+    /// A line contained invalid UTF-8. Holds the line with invalid sequences replaced by U+FFFD.
+    ///
+    /// Only reported if `ParserOptions::warn_invalid_utf8` is set. The line is processed normally
+    /// afterwards.
+    InvalidUtf8(String),
+    /// A comment line. Holds the text after the colon and an optional space.
+    ///
+    /// Servers often send comments to keep idle connections alive.
+    Comment(String),
+    /// A limit from `ParserOptions::limits` was exceeded. See `LimitPolicy` for what happens
+    /// afterwards.
+    LimitExceeded(LimitExceeded),
+}
+
+/// A Server-Sent Event borrowing its fields from an `EventStreamParser`.
+///
+/// Values are taken straight from the parser's input buffer where possible. Only multi-line data
+/// and fields which were split across multiple `feed` calls are copied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventRef<'a> {
+    /// Corresponds to the `id` field.
+    pub id: Option<&'a str>,
+    /// Corresponds to the `event` field.
+    pub event_type: Option<&'a str>,
+    /// All `data` fields concatenated by newlines.
+    pub data: &'a str,
+    /// Corresponds to the `retry` field.
+    pub retry: Option<Duration>,
+    /// See `Event::extra_fields`.
+    pub extra_fields: &'a [(String, String)],
+}
+
+impl<'a> EventRef<'a> {
+    /// Copies the event into an owned `Event`.
+    pub fn into_owned(self) -> Event {
+        Event {
+            id: self.id.map(String::from),
+            event_type: self.event_type.map(String::from),
+            data: self.data.to_string(),
+            retry: self.retry,
+            extra_fields: self.extra_fields.to_vec(),
+        }
+    }
+}
+
+impl<'a> From<EventRef<'a>> for Event {
+    fn from(event: EventRef<'a>) -> Event {
+        event.into_owned()
+    }
+}
+
+/// Borrowed version of `StreamItem`, returned by `EventStreamParser::next_item_ref`.
+#[derive(Debug, PartialEq)]
+pub enum StreamItemRef<'a> {
+    /// A complete event.
+    Event(EventRef<'a>),
+    /// The stream asked for a new reconnection time.
+    Retry(Duration),
+    /// See `StreamItem::InvalidUtf8`.
+    InvalidUtf8(String),
+    /// A comment line.
+    Comment(&'a str),
+    /// See `StreamItem::LimitExceeded`.
+    LimitExceeded(LimitExceeded),
+}
+
+impl<'a> StreamItemRef<'a> {
+    /// Converts the item into an owned `StreamItem`.
+    pub fn into_owned(self) -> StreamItem {
+        match self {
+            StreamItemRef::Event(event) => StreamItem::Event(event.into_owned()),
+            StreamItemRef::Retry(retry) => StreamItem::Retry(retry),
+            StreamItemRef::InvalidUtf8(line) => StreamItem::InvalidUtf8(line),
+            StreamItemRef::Comment(text) => StreamItem::Comment(text.to_string()),
+            StreamItemRef::LimitExceeded(limit) => StreamItem::LimitExceeded(limit),
+        }
+    }
+}
+
+/// How an `EventStreamParser` builds events from fields.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DispatchMode {
+    /// Same behavior as `parse_event_line`: `data` keeps its trailing newline and `id` and `event`
+    /// only apply to the event they appear in.
+    #[default]
+    Legacy,
+    /// Follow the [WHATWG processing model][spec], like browsers do:
+    ///
+    ///  - The final newline of `data` is removed.
+    ///  - Events with an empty data buffer are not dispatched.
+    ///  - The last event ID is kept across events, so `id` is set on every event after the first
+    ///    `id` field. An empty `id` field resets it, and `id` values containing NUL are ignored.
+    ///  - Events without an `event` field get the type `message`.
+    ///  - `retry` values consisting of anything other than ASCII digits are ignored.
+    ///
+    /// [spec]: https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation
+    Whatwg,
+}
+
+/// Options for an `EventStreamParser`.
+#[derive(Debug, Clone, Default)]
+pub struct ParserOptions {
+    /// How fields are combined into events.
+    pub dispatch_mode: DispatchMode,
+    /// Report lines with invalid UTF-8 as `StreamItem::InvalidUtf8`.
+    pub warn_invalid_utf8: bool,
+    /// Collect fields the parser doesn't know in `Event::extra_fields`, e.g. for forwarding
+    /// streams with protocol extensions.
+    pub keep_unknown_fields: bool,
+    /// Bounds on memory use per line and event.
+    pub limits: Limits,
+}
+
+/// Resource limits for an `EventStreamParser`.
+///
+/// All limits are disabled by default. A stream without line breaks can otherwise make the parser
+/// buffer an unbounded amount of data.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// Maximum length of a single line in bytes, excluding the line terminator.
+    pub max_line_length: Option<usize>,
+    /// Maximum size of an event's data in bytes, including the newlines between data lines.
+    pub max_data_size: Option<usize>,
+    /// Maximum number of `data` lines in a single event.
+    pub max_data_lines: Option<usize>,
+    /// What to do when a limit is exceeded.
+    pub policy: LimitPolicy,
+}
+
+/// What an `EventStreamParser` does after reporting `StreamItem::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LimitPolicy {
+    /// Stop parsing. All further input is ignored, so the connection should be closed.
+    #[default]
+    Fail,
+    /// Drop the event the limit was exceeded in and continue with the next one.
+    DropEvent,
+}
+
+/// The limit from `Limits` that a stream exceeded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitExceeded {
+    /// A line was longer than `max_line_length`.
+    LineLength,
+    /// An event's data was larger than `max_data_size`.
+    DataSize,
+    /// An event had more than `max_data_lines` data lines.
+    DataLines,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            LimitExceeded::LineLength => "line too long",
+            LimitExceeded::DataSize => "event data too large",
+            LimitExceeded::DataLines => "too many data lines in event",
+        })
+    }
+}
+
+impl error::Error for LimitExceeded {}
+
+/// UTF-8 encoded U+FEFF.
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Incremental parser for raw event-stream bytes.
+///
+/// Unlike `parse_event_line`, the parser does its own line splitting. Bytes can be fed in chunks
+/// of any size, e.g. as they arrive from a socket. Lines may be terminated by CR, LF or CRLF, even
+/// if a CRLF pair is split across two chunks. A byte order mark at the start of the stream is
+/// skipped and invalid UTF-8 is replaced with U+FFFD.
+///
+/// Events without any fields are not dispatched. See `DispatchMode` for the remaining differences
+/// to browsers.
+///
+/// # Examples
+///
+/// ```
+/// # use eventsource::event::{EventStreamParser, StreamItem};
+/// let mut parser = EventStreamParser::new();
+/// parser.feed(b"id: 42\r");
+/// parser.feed(b"\ndata: foo");
+/// assert_eq!(parser.next_item(), None);
+/// parser.feed(b"bar\n\n");
+/// match parser.next_item() {
+///     Some(StreamItem::Event(event)) => {
+///         assert_eq!(event.id, Some("42".into()));
+///         assert_eq!(event.data, "foobar\n");
+///     }
+///     _ => panic!("expected an event"),
+/// }
+/// assert_eq!(parser.next_item(), None);
+/// ```
+#[derive(Debug, Default)]
+pub struct EventStreamParser {
+    /// Bytes received but not processed yet.
+    buf: Vec<u8>,
+    /// Start of the current line in `buf`.
+    pos: usize,
+    /// Position in `buf` up to which we know there is no line terminator.
+    scanned: usize,
+    /// The previous line ended with CR, so an LF at the start of the next chunk has to be skipped.
+    skip_lf: bool,
+    /// The start of the stream has been checked for a byte order mark.
+    bom_checked: bool,
+    /// An event was returned from the current field values, so they have to be cleared.
+    dispatched: bool,
+    /// Line with invalid UTF-8 to process after returning `InvalidUtf8`.
+    invalid_line: Option<String>,
+    /// The current line is too long and is skipped up to its terminator.
+    discard_line: bool,
+    /// A limit was exceeded with `LimitPolicy::Fail`.
+    failed: bool,
+    /// The last event ID buffer as of the last empty line, only kept in `DispatchMode::Whatwg`.
+    committed_id: Option<String>,
+    fields: FieldState,
+}
+
+/// Field processing part of the parser, kept separate so that it can be borrowed independently
+/// of the input buffer.
+#[derive(Debug, Default)]
+struct FieldState {
+    options: ParserOptions,
+    /// The `id` field. In `DispatchMode::Whatwg`, this is the last event ID buffer, which is kept
+    /// across events.
+    id: Value,
+    event_type: Value,
+    data: Value,
+    retry: Option<Duration>,
+    /// Unknown fields, only collected with `ParserOptions::keep_unknown_fields`.
+    extra_fields: Vec<(String, String)>,
+    data_lines: usize,
+    /// A limit was exceeded in the current event, so its remaining fields are ignored.
+    skip_event: bool,
+    /// Text of the last comment.
+    comment: Value,
+}
+
+/// Result of `FieldState::process_line`.
+enum LineResult {
+    Next,
+    Dispatch,
+    Retry(Duration),
+    /// The line was a comment, its text is in `FieldState::comment`.
+    Comment,
+    LimitExceeded(LimitExceeded),
+}
+
+/// Value of a field in `FieldState`.
+#[derive(Debug, Default)]
+enum Value {
+    #[default]
+    Unset,
+    /// Range of valid UTF-8 in the parser's buffer.
+    Borrowed(Range<usize>),
+    Owned(String),
+}
+
+/// Result of `EventStreamParser::next_line`.
+enum RawLine {
+    Line { start: usize, span: LineSpan },
+    TooLong,
+}
+
+/// Location of a line in the parser's buffer.
+#[derive(Clone, Copy)]
+struct LineSpan {
+    /// End of the line, excluding the terminator.
+    end: usize,
+    /// The line is terminated by LF.
+    lf: bool,
+}
+
+impl EventStreamParser {
+    /// Creates a new parser with default options.
+    pub fn new() -> EventStreamParser {
+        EventStreamParser::default()
+    }
+
+    /// Creates a new parser with the given options.
+    pub fn with_options(options: ParserOptions) -> EventStreamParser {
+        EventStreamParser {
+            fields: FieldState { options, ..FieldState::default() },
+            ..EventStreamParser::default()
+        }
+    }
+
+    /// Returns the current last event ID, which every `id` field updates right away.
+    ///
+    /// Only available in `DispatchMode::Whatwg`. In legacy mode, check the `id` of each event
+    /// instead.
+    pub fn last_event_id(&self) -> Option<&str> {
+        match self.fields.options.dispatch_mode {
+            DispatchMode::Legacy => None,
+            DispatchMode::Whatwg => self.fields.id.get(&self.buf),
+        }
+    }
+
+    /// Returns the last event ID as of the last empty line, which ends a block of fields.
+    ///
+    /// Unlike `last_event_id`, this ignores `id` fields of a block which has not ended yet, like
+    /// browsers do. Send this ID when reconnecting, so that an event cut off by the end of the
+    /// connection is sent again. Blocks without data update it as well.
+    ///
+    /// Only available in `DispatchMode::Whatwg`.
+    pub fn committed_last_event_id(&self) -> Option<&str> {
+        self.committed_id.as_deref()
+    }
+
+    /// Sets the last event ID, e.g. to continue where a previous connection left off.
+    ///
+    /// Has no effect in legacy mode.
+    pub fn set_last_event_id(&mut self, id: Option<String>) {
+        if self.fields.options.dispatch_mode == DispatchMode::Whatwg {
+            let id = id.filter(|id| !id.is_empty());
+            self.fields.id = match id {
+                Some(ref id) => Value::Owned(id.clone()),
+                None => Value::Unset,
+            };
+            self.committed_id = id;
+        }
+    }
+
+    /// Returns whether a limit with `LimitPolicy::Fail` was exceeded.
+    ///
+    /// A failed parser ignores further input.
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Appends a chunk of bytes to the parser's input.
+    ///
+    /// Call `next_item` afterwards until it returns `None` to get all complete items.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        if self.failed {
+            return;
+        }
+        if self.dispatched {
+            self.fields.clear_event();
+            self.dispatched = false;
+        }
+        if self.pos > 0 {
+            // Field values must not point into the part of the buffer we are about to remove.
+            self.fields.detach(&self.buf);
+            self.buf.drain(..self.pos);
+            self.scanned -= self.pos;
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Takes over the last event ID buffer at the end of a block.
+    fn commit_last_event_id(&mut self) {
+        if self.fields.options.dispatch_mode != DispatchMode::Whatwg {
+            return;
+        }
+        let id = self.fields.id.get(&self.buf);
+        if self.committed_id.as_deref() != id {
+            self.committed_id = id.map(str::to_string);
+        }
+    }
+
+    /// Returns the next complete item, or `None` if more input is needed.
+    pub fn next_item(&mut self) -> Option<StreamItem> {
+        self.next_item_ref().map(StreamItemRef::into_owned)
+    }
+
+    /// Like `next_item`, but returns events borrowing from the parser instead of allocating.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use eventsource::event::{EventStreamParser, StreamItemRef};
+    /// let mut parser = EventStreamParser::new();
+    /// parser.feed(b"event: foo\ndata: bar\n\n");
+    /// match parser.next_item_ref() {
+    ///     Some(StreamItemRef::Event(event)) => {
+    ///         assert_eq!(event.event_type, Some("foo"));
+    ///         assert_eq!(event.data, "bar\n");
+    ///     }
+    ///     _ => panic!("expected an event"),
+    /// }
+    /// ```
+    pub fn next_item_ref(&mut self) -> Option<StreamItemRef<'_>> {
+        if self.failed {
+            return None;
+        }
+        if self.dispatched {
+            self.fields.clear_event();
+            self.dispatched = false;
+        }
+        if !self.bom_checked {
+            let start = &self.buf[self.pos..];
+            if start.len() < BOM.len() && BOM.starts_with(start) {
+                // Could still become a BOM.
+                return None;
+            }
+            if start.starts_with(BOM) {
+                self.pos += BOM.len();
+                self.scanned = self.scanned.max(self.pos);
+            }
+            self.bom_checked = true;
+        }
+
+        loop {
+            let result = if let Some(line) = self.invalid_line.take() {
+                self.fields.process_line(&self.buf, &line, None)
+            } else {
+                match self.next_line()? {
+                    RawLine::TooLong => self.fields.limit_exceeded(LimitExceeded::LineLength),
+                    RawLine::Line { start, span } => {
+                        let bytes = &self.buf[start..span.end];
+                        match str::from_utf8(bytes) {
+                            Ok(line) => self.fields.process_line(&self.buf, line, Some(span)),
+                            Err(_) => {
+                                let line = String::from_utf8_lossy(bytes).into_owned();
+                                if self.fields.options.warn_invalid_utf8 {
+                                    self.invalid_line = Some(line.clone());
+                                    return Some(StreamItemRef::InvalidUtf8(line));
+                                }
+                                self.fields.process_line(&self.buf, &line, None)
+                            }
+                        }
+                    }
+                }
+            };
+
+            match result {
+                LineResult::Next => (),
+                LineResult::Retry(retry) => return Some(StreamItemRef::Retry(retry)),
+                LineResult::Comment => {
+                    let text = self.fields.comment.get(&self.buf).unwrap_or_default();
+                    return Some(StreamItemRef::Comment(text));
+                }
+                LineResult::Dispatch => {
+                    self.commit_last_event_id();
+                    if self.fields.has_event() {
+                        self.dispatched = true;
+                        return Some(StreamItemRef::Event(self.fields.event_ref(&self.buf)));
+                    }
+                    self.fields.clear_event();
+                }
+                LineResult::LimitExceeded(limit) => {
+                    if self.fields.options.limits.policy == LimitPolicy::Fail {
+                        self.failed = true;
+                        // The last event ID stays available after freeing the buffer.
+                        self.fields.detach(&self.buf);
+                        self.buf = Vec::new();
+                        self.pos = 0;
+                        self.scanned = 0;
+                    }
+                    return Some(StreamItemRef::LimitExceeded(limit));
+                }
+            }
+        }
+    }
+
+    /// Finds the next complete line in the buffer, enforcing `Limits::max_line_length`.
+    fn next_line(&mut self) -> Option<RawLine> {
+        let max_line_length = self.fields.options.limits.max_line_length;
+        loop {
+            if self.skip_lf && self.pos < self.buf.len() {
+                if self.buf[self.pos] == b'\n' {
+                    self.pos += 1;
+                }
+                self.skip_lf = false;
+                self.scanned = self.scanned.max(self.pos);
+            }
+
+            let end = match self.buf[self.scanned..]
+                .iter()
+                .position(|&b| b == b'\r' || b == b'\n')
+            {
+                Some(i) => self.scanned + i,
+                None => {
+                    self.scanned = self.buf.len();
+                    if self.discard_line {
+                        self.pos = self.buf.len();
+                        return None;
+                    }
+                    if max_line_length.is_some_and(|max| self.buf.len() - self.pos > max) {
+                        // Don't wait for the end of the line, it may never come.
+                        self.discard_line = true;
+                        self.pos = self.buf.len();
+                        return Some(RawLine::TooLong);
+                    }
+                    return None;
+                }
+            };
+            let start = self.pos;
+            let lf = self.buf[end] == b'\n';
+            self.skip_lf = !lf;
+            self.pos = end + 1;
+            self.scanned = self.pos;
+
+            if mem::replace(&mut self.discard_line, false) {
+                // End of a line which was already reported as too long.
+                continue;
+            }
+            if max_line_length.is_some_and(|max| end - start > max) {
+                return Some(RawLine::TooLong);
+            }
+            return Some(RawLine::Line { start, span: LineSpan { end, lf } });
+        }
+    }
+}
+
+/// Reads events from any `BufRead`, e.g. a file, stdin or a TCP stream.
+///
+/// Iterating yields all items produced by the underlying `EventStreamParser` until the reader
+/// reaches EOF or the parser fails. An incomplete event at the end of the input is dropped.
+///
+/// # Examples
+///
+/// ```
+/// # use eventsource::event::{EventReader, StreamItem};
+/// let input = b"data: foo\n\nretry: 100\n" as &[u8];
+/// for item in EventReader::new(input) {
+///     match item.unwrap() {
+///         StreamItem::Event(event) => println!("{}", event),
+///         StreamItem::Retry(retry) => println!("retry: {:?}", retry),
+///         _ => (),
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct EventReader<R> {
+    reader: R,
+    parser: EventStreamParser,
+}
+
+impl<R: BufRead> EventReader<R> {
+    /// Creates a reader with default parser options.
+    pub fn new(reader: R) -> EventReader<R> {
+        EventReader::with_parser(reader, EventStreamParser::new())
+    }
+
+    /// Creates a reader with the given parser options.
+    pub fn with_options(reader: R, options: ParserOptions) -> EventReader<R> {
+        EventReader::with_parser(reader, EventStreamParser::with_options(options))
+    }
+
+    /// Creates a reader feeding into an existing parser.
+    pub fn with_parser(reader: R, parser: EventStreamParser) -> EventReader<R> {
+        EventReader { reader, parser }
+    }
+
+    /// Returns a reference to the parser.
+    pub fn parser(&self) -> &EventStreamParser {
+        &self.parser
+    }
+
+    /// Returns a mutable reference to the parser.
+    pub fn parser_mut(&mut self) -> &mut EventStreamParser {
+        &mut self.parser
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the underlying reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: BufRead> Iterator for EventReader<R> {
+    type Item = io::Result<StreamItem>;
+
+    fn next(&mut self) -> Option<io::Result<StreamItem>> {
+        loop {
+            if let Some(item) = self.parser.next_item() {
+                return Some(Ok(item));
+            }
+            if self.parser.is_failed() {
+                return None;
+            }
+            let len = match self.reader.fill_buf() {
+                Ok([]) => return None,
+                Ok(buf) => {
+                    self.parser.feed(buf);
+                    buf.len()
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Some(Err(err)),
+            };
+            self.reader.consume(len);
+        }
+    }
+}
+
+impl FieldState {
+    /// Processes a single line without terminator. `span` is set if `line` is part of `buf`.
+    fn process_line(&mut self, buf: &[u8], line: &str, span: Option<LineSpan>) -> LineResult {
+        if line.is_empty() {
+            // Fields of a dropped event were not kept, so this will not dispatch anything.
+            self.skip_event = false;
+            return LineResult::Dispatch;
+        }
+        let (field, value) = split_field(line);
+        if self.skip_event && !field.is_empty() {
+            return LineResult::Next;
+        }
+        if field == "data" {
+            if let Some(limit) = self.data_limit(value) {
+                return self.limit_exceeded(limit);
+            }
+            self.data_lines += 1;
+        }
+        // The value is always at the end of the line.
+        let value_range = span.map(|span| span.end - value.len()..span.end);
+        let new_value = || match value_range.clone() {
+            Some(range) => Value::Borrowed(range),
+            None => Value::Owned(value.to_string()),
+        };
+
+        match (field, self.options.dispatch_mode) {
+            ("", _) => {
+                self.comment = new_value();
+                return LineResult::Comment;
+            }
+            ("event", DispatchMode::Legacy) => self.event_type = new_value(),
+            ("event", DispatchMode::Whatwg) => {
+                self.event_type = if value.is_empty() { Value::Unset } else { new_value() };
+            }
+            ("data", DispatchMode::Legacy) => match (&self.data, span) {
+                // Keep the line terminator if it is a plain LF.
+                (Value::Unset, Some(span)) if span.lf => {
+                    self.data = Value::Borrowed(span.end - value.len()..span.end + 1);
+                }
+                _ => {
+                    let data = self.data.to_mut(buf);
+                    data.push_str(value);
+                    data.push('\n');
+                }
+            },
+            ("data", DispatchMode::Whatwg) => {
+                if let Value::Unset = self.data {
+                    self.data = new_value();
+                } else {
+                    let data = self.data.to_mut(buf);
+                    data.push('\n');
+                    data.push_str(value);
+                }
+            }
+            ("id", DispatchMode::Legacy) => self.id = new_value(),
+            ("id", DispatchMode::Whatwg) if !value.contains('\0') => {
+                self.id = if value.is_empty() { Value::Unset } else { new_value() };
+            }
+            ("retry", DispatchMode::Legacy) => {
+                if let Ok(retry) = value.parse::<u64>() {
+                    let retry = Duration::from_millis(retry);
+                    self.retry = Some(retry);
+                    return LineResult::Retry(retry);
+                }
+            }
+            ("retry", DispatchMode::Whatwg)
+                if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                // Values too large for u64 are not useful either way.
+                if let Ok(retry) = value.parse::<u64>() {
+                    let retry = Duration::from_millis(retry);
+                    self.retry = Some(retry);
+                    return LineResult::Retry(retry);
+                }
+            }
+            ("id", _) | ("retry", _) => (), // invalid value
+            _ => {
+                if self.options.keep_unknown_fields {
+                    self.extra_fields.push((field.to_string(), value.to_string()));
+                }
+            }
+        }
+        LineResult::Next
+    }
+
+    /// Checks whether adding another data line with `value` would exceed a limit.
+    fn data_limit(&self, value: &str) -> Option<LimitExceeded> {
+        let limits = &self.options.limits;
+        if limits.max_data_lines.is_some_and(|max| self.data_lines >= max) {
+            return Some(LimitExceeded::DataLines);
+        }
+        let newline = match self.options.dispatch_mode {
+            DispatchMode::Legacy => 1,
+            DispatchMode::Whatwg => self.data.is_set() as usize,
+        };
+        let size = self.data.len() + newline + value.len();
+        if limits.max_data_size.is_some_and(|max| size > max) {
+            return Some(LimitExceeded::DataSize);
+        }
+        None
+    }
+
+    /// Drops the current event after exceeding a limit.
+    fn limit_exceeded(&mut self, limit: LimitExceeded) -> LineResult {
+        self.clear_event();
+        self.skip_event = true;
+        LineResult::LimitExceeded(limit)
+    }
+
+    /// Returns whether an event should be dispatched for the current fields.
+    fn has_event(&self) -> bool {
+        match self.options.dispatch_mode {
+            DispatchMode::Legacy => {
+                self.id.is_set()
+                    || self.event_type.is_set()
+                    || self.data.is_set()
+                    || self.retry.is_some()
+                    || !self.extra_fields.is_empty()
+            }
+            DispatchMode::Whatwg => self.data.is_set(),
+        }
+    }
+
+    fn event_ref<'a>(&'a self, buf: &'a [u8]) -> EventRef<'a> {
+        let event_type = self.event_type.get(buf);
+        EventRef {
+            id: self.id.get(buf),
+            event_type: match self.options.dispatch_mode {
+                DispatchMode::Legacy => event_type,
+                DispatchMode::Whatwg => event_type.or(Some("message")),
+            },
+            data: self.data.get(buf).unwrap_or(""),
+            retry: self.retry,
+            extra_fields: &self.extra_fields,
+        }
+    }
+
+    /// Resets the fields for the next event.
+    fn clear_event(&mut self) {
+        if self.options.dispatch_mode == DispatchMode::Legacy {
+            self.id = Value::Unset;
+        }
+        self.event_type = Value::Unset;
+        self.data = Value::Unset;
+        self.retry = None;
+        self.extra_fields.clear();
+        self.data_lines = 0;
+    }
+
+    /// Copies all values borrowed from `buf`.
+    fn detach(&mut self, buf: &[u8]) {
+        self.id.detach(buf);
+        self.event_type.detach(buf);
+        self.data.detach(buf);
+    }
+}
+
+impl Value {
+    fn is_set(&self) -> bool {
+        !matches!(self, Value::Unset)
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Value::Unset => 0,
+            Value::Borrowed(range) => range.len(),
+            Value::Owned(value) => value.len(),
+        }
+    }
+
+    fn get<'a>(&'a self, buf: &'a [u8]) -> Option<&'a str> {
+        match self {
+            Value::Unset => None,
+            Value::Borrowed(range) => {
+                Some(str::from_utf8(&buf[range.clone()]).expect("borrowed values are valid UTF-8"))
+            }
+            Value::Owned(value) => Some(value),
+        }
+    }
+
+    fn detach(&mut self, buf: &[u8]) {
+        if let Value::Borrowed(_) = self {
+            let value = self.get(buf).unwrap_or_default().to_string();
+            *self = Value::Owned(value);
+        }
+    }
+
+    /// Returns the value as `String` for appending, copying it if necessary.
+    fn to_mut(&mut self, buf: &[u8]) -> &mut String {
+        match self {
+            Value::Unset => *self = Value::Owned(String::new()),
+            Value::Borrowed(_) => self.detach(buf),
+            Value::Owned(_) => (),
+        }
+        match self {
+            Value::Owned(value) => value,
+            _ => unreachable!(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -126,22 +1136,444 @@ mod tests {
     fn basic_event_display() {
         assert_eq!(
             "data: hello world\n",
-            Event { id: None, event_type: None, data: "hello world".to_string() }.to_string());
+            Event { id: None, event_type: None, data: "hello world".to_string(), ..Event::new() }.to_string());
         assert_eq!(
             "id: foo\ndata: hello world\n",
-            Event { id: Some("foo".to_string()), event_type: None, data: "hello world".to_string() }.to_string());
+            Event { id: Some("foo".to_string()), event_type: None, data: "hello world".to_string(), ..Event::new() }.to_string());
         assert_eq!(
             "event: bar\ndata: hello world\n",
-            Event { id: None, event_type: Some("bar".to_string()), data: "hello world".to_string() }.to_string());
+            Event { id: None, event_type: Some("bar".to_string()), data: "hello world".to_string(), ..Event::new() }.to_string());
     }
 
     #[test]
     fn multiline_event_display() {
         assert_eq!(
             "data: hello\ndata: world\n",
-            Event { id: None, event_type: None, data: "hello\nworld".to_string() }.to_string());
+            Event { id: None, event_type: None, data: "hello\nworld".to_string(), ..Event::new() }.to_string());
         assert_eq!(
             "data: hello\ndata: \ndata: world\n",
-            Event { id: None, event_type: None, data: "hello\n\nworld".to_string() }.to_string());
+            Event { id: None, event_type: None, data: "hello\n\nworld".to_string(), ..Event::new() }.to_string());
+    }
+
+    fn parse_chunks(chunks: &[&[u8]]) -> Vec<StreamItem> {
+        let mut parser = EventStreamParser::new();
+        let mut items = Vec::new();
+        for chunk in chunks {
+            parser.feed(chunk);
+            while let Some(item) = parser.next_item() {
+                items.push(item);
+            }
+        }
+        items
+    }
+
+    fn data_event(data: &str) -> StreamItem {
+        StreamItem::Event(Event { id: None, event_type: None, data: data.to_string(), ..Event::new() })
+    }
+
+    #[test]
+    fn parser_line_terminators() {
+        let expected = vec![data_event("a\n"), data_event("b\n"), data_event("c\n")];
+        assert_eq!(parse_chunks(&[b"data: a\n\ndata: b\r\rdata: c\r\n\r\n"]), expected);
+        // CRLF split across chunks must not produce an extra empty line.
+        assert_eq!(
+            parse_chunks(&[b"data: a\r", b"\ndata: b\r", b"\n\r", b"\ndata: c\r\n", b"\r\n"]),
+            vec![data_event("a\nb\n"), data_event("c\n")]);
+    }
+
+    #[test]
+    fn parser_byte_by_byte() {
+        let input = b"retry: 100\r\nid: 1\revent: foo\ndata: x\r\n\r\ndata: y\n\n";
+        let chunks: Vec<&[u8]> = input.chunks(1).collect();
+        assert_eq!(parse_chunks(&chunks), vec![
+            StreamItem::Retry(Duration::from_millis(100)),
+            StreamItem::Event(Event {
+                id: Some("1".into()),
+                event_type: Some("foo".into()),
+                data: "x\n".into(),
+                retry: Some(Duration::from_millis(100)),
+                ..Event::new()
+            }),
+            data_event("y\n"),
+        ]);
+    }
+
+    #[test]
+    fn parser_incomplete_event() {
+        assert_eq!(parse_chunks(&[b"\n\ndata: a\n", b"data: b"]), vec![]);
+    }
+
+    fn parse_whatwg(input: &[u8]) -> Vec<StreamItem> {
+        let mut parser = EventStreamParser::with_options(ParserOptions {
+            dispatch_mode: DispatchMode::Whatwg,
+            ..ParserOptions::default()
+        });
+        parser.feed(input);
+        let mut items = Vec::new();
+        while let Some(item) = parser.next_item() {
+            items.push(item);
+        }
+        items
+    }
+
+    fn event(id: Option<&str>, event_type: &str, data: &str) -> Event {
+        Event {
+            id: id.map(Into::into),
+            event_type: Some(event_type.into()),
+            data: data.into(),
+            ..Event::new()
+        }
+    }
+
+    fn whatwg_event(id: Option<&str>, event_type: &str, data: &str) -> StreamItem {
+        StreamItem::Event(event(id, event_type, data))
+    }
+
+    #[test]
+    fn whatwg_data() {
+        assert_eq!(
+            parse_whatwg(b"data: a\ndata\ndata:b\n\nevent: x\n\ndata\n\n: comment\n\n"),
+            vec![
+                whatwg_event(None, "message", "a\n\nb"),
+                whatwg_event(None, "message", ""),
+                StreamItem::Comment("comment".into()),
+            ]);
+    }
+
+    #[test]
+    fn whatwg_last_event_id() {
+        assert_eq!(
+            parse_whatwg(b"id: 1\ndata: a\n\ndata: b\n\nid: x\0y\ndata: c\n\nid\ndata: d\n\n"),
+            vec![
+                whatwg_event(Some("1"), "message", "a"),
+                whatwg_event(Some("1"), "message", "b"),
+                whatwg_event(Some("1"), "message", "c"),
+                whatwg_event(None, "message", "d"),
+            ]);
+        // The ID is updated even if no event is dispatched.
+        let mut parser = EventStreamParser::with_options(ParserOptions {
+            dispatch_mode: DispatchMode::Whatwg,
+            ..ParserOptions::default()
+        });
+        parser.feed(b"id: 2\n\n");
+        assert_eq!(parser.next_item(), None);
+        assert_eq!(parser.last_event_id(), Some("2"));
+        assert_eq!(parser.committed_last_event_id(), Some("2"));
+
+        // Only an empty line commits the ID.
+        parser.feed(b"id: 3\ndata: c\n");
+        assert_eq!(parser.next_item(), None);
+        assert_eq!(parser.last_event_id(), Some("3"));
+        assert_eq!(parser.committed_last_event_id(), Some("2"));
+        parser.feed(b"\n");
+        assert_eq!(parser.next_item(), Some(whatwg_event(Some("3"), "message", "c")));
+        assert_eq!(parser.committed_last_event_id(), Some("3"));
+        parser.set_last_event_id(None);
+        assert_eq!(parser.committed_last_event_id(), None);
+    }
+
+    #[test]
+    fn whatwg_event_type_and_retry() {
+        assert_eq!(
+            parse_whatwg(b"retry: +5\nretry: 5x\nretry\nretry: 7\nevent: foo\ndata: a\n\n"),
+            vec![
+                StreamItem::Retry(Duration::from_millis(7)),
+                StreamItem::Event(Event {
+                    retry: Some(Duration::from_millis(7)),
+                    ..event(None, "foo", "a")
+                }),
+            ]);
+    }
+
+    #[test]
+    fn parser_bom() {
+        assert_eq!(parse_chunks(&[b"\xEF\xBBdata: a\n\n"]), vec![]);
+        assert_eq!(parse_chunks(&[b"\xEF\xBB\xBFdata: a\n\n"]), vec![data_event("a\n")]);
+        assert_eq!(parse_chunks(&[b"\xEF", b"\xBB", b"\xBFdata: a\n\n"]), vec![data_event("a\n")]);
+        // Only a single BOM is stripped.
+        assert_eq!(parse_chunks(&[b"\xEF\xBB\xBF\xEF\xBB\xBFdata: a\n\n"]), vec![]);
+    }
+
+    #[test]
+    fn parser_invalid_utf8() {
+        assert_eq!(parse_chunks(&[b"data: a\xFFb\n\n"]), vec![data_event("a\u{FFFD}b\n")]);
+
+        let mut parser = EventStreamParser::with_options(ParserOptions {
+            warn_invalid_utf8: true,
+            ..ParserOptions::default()
+        });
+        parser.feed(b"data: a\xFF\n\n");
+        assert_eq!(parser.next_item(), Some(StreamItem::InvalidUtf8("data: a\u{FFFD}".into())));
+        assert_eq!(parser.next_item(), Some(data_event("a\u{FFFD}\n")));
+        assert_eq!(parser.next_item(), None);
+    }
+
+    #[test]
+    fn parser_event_ref() {
+        let mut parser = EventStreamParser::new();
+        parser.feed(b"id: 1\nevent: foo\ndata: a\n\ndata: b\r\ndata: c\n\n");
+        let event = match parser.next_item_ref() {
+            Some(StreamItemRef::Event(event)) => event,
+            item => panic!("expected an event, got {:?}", item),
+        };
+        assert_eq!(event, EventRef {
+            id: Some("1"),
+            event_type: Some("foo"),
+            data: "a\n",
+            retry: None,
+            extra_fields: &[],
+        });
+        assert_eq!(Event::from(event), Event {
+            id: Some("1".into()),
+            event_type: Some("foo".into()),
+            data: "a\n".into(),
+            ..Event::new()
+        });
+        match parser.next_item_ref() {
+            Some(StreamItemRef::Event(event)) => assert_eq!(event.data, "b\nc\n"),
+            item => panic!("expected an event, got {:?}", item),
+        }
+    }
+
+    #[test]
+    fn parser_fields_across_feeds() {
+        let mut parser = EventStreamParser::with_options(ParserOptions {
+            dispatch_mode: DispatchMode::Whatwg,
+            ..ParserOptions::default()
+        });
+        parser.feed(b"id: 1\nevent: foo\ndata: a\n");
+        assert_eq!(parser.next_item_ref(), None);
+        parser.feed(b"data: b\n\ndata: c\n\n");
+        assert_eq!(parser.next_item(), Some(whatwg_event(Some("1"), "foo", "a\nb")));
+        assert_eq!(parser.next_item(), Some(whatwg_event(Some("1"), "message", "c")));
+        parser.feed(b"data: d\n\n");
+        assert_eq!(parser.next_item(), Some(whatwg_event(Some("1"), "message", "d")));
+    }
+
+    #[test]
+    fn comments() {
+        let mut event = Event::new();
+        assert_eq!(parse_event_line(":", &mut event), ParseResult::Comment("".into()));
+        assert_eq!(parse_event_line(":  two\n", &mut event), ParseResult::Comment(" two".into()));
+        assert!(event.is_empty());
+
+        assert_eq!(parse_chunks(&[b": keepalive\r\n:x\xFF\n\n"]), vec![
+            StreamItem::Comment("keepalive".into()),
+            StreamItem::Comment("x\u{FFFD}".into()),
+        ]);
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let input = b"ts: 1\ndata: a\nseq:2\nfoo\n\n";
+        assert_eq!(parse_chunks(&[input]), vec![data_event("a\n")]);
+
+        let mut parser = EventStreamParser::with_options(ParserOptions {
+            keep_unknown_fields: true,
+            ..ParserOptions::default()
+        });
+        parser.feed(input);
+        let event = match parser.next_item() {
+            Some(StreamItem::Event(event)) => event,
+            item => panic!("expected an event, got {:?}", item),
+        };
+        assert_eq!(event.extra_fields, vec![
+            ("ts".to_string(), "1".to_string()),
+            ("seq".to_string(), "2".to_string()),
+            ("foo".to_string(), "".to_string()),
+        ]);
+        assert_eq!(event.to_string(), "ts: 1\nseq: 2\nfoo: \ndata: a\n");
+        assert_eq!(parser.next_item(), None);
+    }
+
+    #[test]
+    fn retry_round_trip() {
+        let input = "id: 1\nevent: foo\nretry: 1500\ndata: a\ndata: b\n";
+        let mut event = Event::new();
+        for line in input.lines() {
+            assert_ne!(parse_event_line(line, &mut event), ParseResult::Dispatch);
+        }
+        assert_eq!(event.retry, Some(Duration::from_millis(1500)));
+        assert_eq!(event.to_string(), input);
+
+        // A retry field on its own is still an event.
+        assert_eq!(parse_chunks(&[b"retry: 10\n\n"]), vec![
+            StreamItem::Retry(Duration::from_millis(10)),
+            StreamItem::Event(Event { retry: Some(Duration::from_millis(10)), ..Event::new() }),
+        ]);
+    }
+
+    fn write_event(event: &Event) -> io::Result<String> {
+        let mut writer = EventWriter::new(Vec::new());
+        writer.write_event(event)?;
+        Ok(String::from_utf8(writer.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn writer_data_lines() {
+        let event = |data: &str| Event { data: data.into(), ..Event::new() };
+        assert_eq!(write_event(&event("")).unwrap(), "\n");
+        assert_eq!(write_event(&event("a\n")).unwrap(), "data: a\n\n");
+        assert_eq!(
+            write_event(&event("a\rb\r\nc\n\nd\r\r")).unwrap(),
+            "data: a\ndata: b\ndata: c\ndata: \ndata: d\ndata: \n\n");
+    }
+
+    #[test]
+    fn writer_round_trip() {
+        let event = Event {
+            id: Some("1".into()),
+            event_type: Some("foo".into()),
+            data: "a\rb\n".into(),
+            retry: Some(Duration::from_millis(100)),
+            extra_fields: vec![("ts".into(), "2".into())],
+        };
+        let mut parser = EventStreamParser::with_options(ParserOptions {
+            keep_unknown_fields: true,
+            ..ParserOptions::default()
+        });
+        parser.feed(write_event(&event).unwrap().as_bytes());
+        assert_eq!(parser.next_item(), Some(StreamItem::Retry(Duration::from_millis(100))));
+        assert_eq!(parser.next_item(), Some(StreamItem::Event(Event { data: "a\nb\n".into(), ..event })));
+        assert_eq!(parser.next_item(), None);
+    }
+
+    #[test]
+    fn writer_rejects_injection() {
+        let invalid = vec![
+            Event { id: Some("1\ndata: x".into()), ..Event::new() },
+            Event { id: Some("1\r".into()), ..Event::new() },
+            Event { id: Some("1\0".into()), ..Event::new() },
+            Event { event_type: Some("a\r\n\r\ndata: x".into()), ..Event::new() },
+            Event { extra_fields: vec![("data: x\nfoo".into(), "".into())], ..Event::new() },
+            Event { extra_fields: vec![("".into(), "x".into())], ..Event::new() },
+            Event { extra_fields: vec![("foo".into(), "\n\n".into())], ..Event::new() },
+            Event { extra_fields: vec![("event".into(), "x".into())], ..Event::new() },
+            Event { extra_fields: vec![("data".into(), "x".into())], ..Event::new() },
+            Event { extra_fields: vec![("id".into(), "x".into())], ..Event::new() },
+            Event { extra_fields: vec![("retry".into(), "1".into())], ..Event::new() },
+        ];
+        for event in invalid {
+            let mut writer = EventWriter::new(Vec::new());
+            let err = writer.write_event(&event).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(writer.get_ref().is_empty());
+        }
+    }
+
+    #[test]
+    fn writer_comments() {
+        let mut writer = EventWriter::new(Vec::new());
+        writer.write_comment("").unwrap();
+        writer.write_comment("a\nb").unwrap();
+        assert_eq!(writer.into_inner(), b":\n: a\n: b\n");
+    }
+
+    fn parse_with_limits(limits: Limits, chunks: &[&[u8]]) -> Vec<StreamItem> {
+        let mut parser = EventStreamParser::with_options(ParserOptions {
+            limits,
+            ..ParserOptions::default()
+        });
+        let mut items = Vec::new();
+        for chunk in chunks {
+            parser.feed(chunk);
+            while let Some(item) = parser.next_item() {
+                items.push(item);
+            }
+        }
+        items
+    }
+
+    #[test]
+    fn limit_line_length() {
+        let limits = Limits {
+            max_line_length: Some(10),
+            policy: LimitPolicy::DropEvent,
+            ..Limits::default()
+        };
+        assert_eq!(
+            parse_with_limits(limits, &[b"data: 0123\n\nid: 1\ndata: 0123456789\n", b"data: x\n\ndata: y\n\n"]),
+            vec![
+                data_event("0123\n"),
+                StreamItem::LimitExceeded(LimitExceeded::LineLength),
+                data_event("y\n"),
+            ]);
+        // Lines without terminator are not buffered indefinitely.
+        assert_eq!(
+            parse_with_limits(limits, &[b"data: 0123", b"456789", b"abc\r", b"\ndata: y\n\n"]),
+            vec![StreamItem::LimitExceeded(LimitExceeded::LineLength)]);
+        assert_eq!(
+            parse_with_limits(limits, &[b"data: 0123", b"456789", b"abc\r", b"\n\ndata: y\n\n"]),
+            vec![StreamItem::LimitExceeded(LimitExceeded::LineLength), data_event("y\n")]);
+    }
+
+    #[test]
+    fn limit_data() {
+        let limits = Limits {
+            max_data_size: Some(4),
+            policy: LimitPolicy::DropEvent,
+            ..Limits::default()
+        };
+        assert_eq!(
+            parse_with_limits(limits, &[b"data: a\ndata: b\n\ndata: abcd\n\ndata: abc\n\n"]),
+            vec![
+                data_event("a\nb\n"),
+                StreamItem::LimitExceeded(LimitExceeded::DataSize),
+                data_event("abc\n"),
+            ]);
+
+        let limits = Limits {
+            max_data_lines: Some(2),
+            policy: LimitPolicy::DropEvent,
+            ..Limits::default()
+        };
+        assert_eq!(
+            parse_with_limits(limits, &[b"data\ndata\ndata\ndata\n\ndata\n\n"]),
+            vec![StreamItem::LimitExceeded(LimitExceeded::DataLines), data_event("\n")]);
+    }
+
+    #[test]
+    fn limit_policy_fail() {
+        let limits = Limits { max_data_lines: Some(1), ..Limits::default() };
+        assert_eq!(
+            parse_with_limits(limits, &[b"data\ndata\n\ndata\n\n", b"data\n\n"]),
+            vec![StreamItem::LimitExceeded(LimitExceeded::DataLines)]);
+
+        let mut parser = EventStreamParser::with_options(ParserOptions {
+            dispatch_mode: DispatchMode::Whatwg,
+            limits,
+            ..ParserOptions::default()
+        });
+        parser.feed(b"id: 1\ndata\ndata\n");
+        assert_eq!(parser.next_item(), Some(StreamItem::LimitExceeded(LimitExceeded::DataLines)));
+        assert_eq!(parser.last_event_id(), Some("1"));
+    }
+
+    #[test]
+    fn event_reader() {
+        let input = b"data: a\r\n\r\nretry: 5\r: c\rdata: b\n" as &[u8];
+        let reader = io::BufReader::with_capacity(3, input);
+        let items: Vec<_> = EventReader::new(reader).map(Result::unwrap).collect();
+        assert_eq!(items, vec![
+            data_event("a\n"),
+            StreamItem::Retry(Duration::from_millis(5)),
+            StreamItem::Comment("c".into()),
+        ]);
+    }
+
+    #[test]
+    fn event_reader_stops_on_failure() {
+        let options = ParserOptions {
+            limits: Limits { max_data_lines: Some(1), ..Limits::default() },
+            ..ParserOptions::default()
+        };
+        let input = b"data\ndata\ndata: rest\n\n" as &[u8];
+        let mut reader = EventReader::with_options(io::BufReader::with_capacity(5, input), options);
+        let items: Vec<_> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(items, vec![StreamItem::LimitExceeded(LimitExceeded::DataLines)]);
+        assert!(reader.parser().is_failed());
+        let mut rest = String::new();
+        io::Read::read_to_string(&mut reader.into_inner(), &mut rest).unwrap();
+        assert_eq!(rest, "data: rest\n\n");
     }
 }
@@ -22,6 +22,26 @@
 // Generic text/event-stream parsing and serialization.
 pub mod event;
 
+// Persistent last event IDs for the HTTP interface
+#[cfg(feature = "with-reqwest")]
+pub mod store;
+
 // HTTP interface
 #[cfg(feature = "with-reqwest")]
 pub mod reqwest;
+
+// Reconnection policies for the HTTP interface
+#[cfg(feature = "with-reqwest")]
+pub mod retry;
+
+// Metrics for the HTTP interface
+#[cfg(feature = "with-reqwest")]
+pub mod stats;
+
+// Browser-like event listeners for the HTTP interface
+#[cfg(feature = "with-reqwest")]
+pub mod dispatch;
+
+// Async HTTP interface
+#[cfg(feature = "with-reqwest-async")]
+pub mod reqwest_async;
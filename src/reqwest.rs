@@ -1,5 +1,6 @@
 //! # Reqwest-based EventSource client
 
+#[allow(unexpected_cfgs)]
 mod errors {
     use error_chain::*;
     error_chain! {
@@ -17,36 +18,178 @@ mod errors {
                 description("unexpected Content-Type header")
                 display("unexpected Content-Type: {}", mime_type)
             }
+            MalformedContentType(value: String) {
+                description("malformed Content-Type header")
+                display("malformed Content-Type: {}", value)
+            }
             NoContentType {
                 description("no Content-Type header in response")
                 display("Content-Type missing")
             }
+            LimitExceeded(limit: crate::event::LimitExceeded) {
+                description("event stream limit exceeded")
+                display("event stream limit exceeded: {}", limit)
+            }
+            InvalidUtf8(line: String) {
+                description("invalid UTF-8 in event stream")
+                display("invalid UTF-8 in line: {}", line)
+            }
+            IdleTimeout(timeout: std::time::Duration) {
+                description("connection idle for too long")
+                display("no data received for {:?}", timeout)
+            }
+            GaveUp(reason: crate::retry::GiveUpReason) {
+                description("gave up reconnecting")
+                display("gave up reconnecting: {}", reason)
+            }
         }
     }
 }
 pub use self::errors::*;
 
+impl Error {
+    /// Returns whether clients stop reconnecting after this error.
+    ///
+    /// Like browsers, clients give up on an unsuccessful status code or a wrong Content-Type.
+    /// Network errors and problems in the event stream are retryable.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self.kind(),
+            ErrorKind::Http(_)
+                | ErrorKind::InvalidContentType(_)
+                | ErrorKind::MalformedContentType(_)
+                | ErrorKind::NoContentType
+                | ErrorKind::GaveUp(_)
+        )
+    }
+
+    /// Returns whether clients reconnect after this error, the opposite of `is_fatal`.
+    pub fn is_retryable(&self) -> bool {
+        !self.is_fatal()
+    }
+}
+
 use reqwest::blocking as reqw;
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE};
-use super::event::{parse_event_line, Event, ParseResult};
-use std::io::{BufRead, BufReader};
-use std::time::{Duration, Instant};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, CACHE_CONTROL, CONTENT_TYPE};
+use reqwest::{Method, StatusCode};
+use super::event::{
+    DispatchMode, Event, EventReader, EventStreamParser, LimitPolicy, ParserOptions, StreamItem,
+};
+use super::retry::{GiveUpReason, RetryLimits, RetryPolicy, RetryState};
+use super::stats::{ClientStats, Metrics};
+use super::store::LastEventIdStore;
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, BufReader, Cursor, Read};
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 const DEFAULT_RETRY: u64 = 5000;
 
+pub(crate) type CommentHandler = Box<dyn FnMut(&str) + Send>;
+pub(crate) type RequestHandler = Box<dyn FnMut(&mut HeaderMap) + Send>;
+pub(crate) type AuthErrorHandler = Box<dyn FnMut(StatusCode) -> bool + Send>;
+pub(crate) type OpenHandler = Box<dyn FnMut(u32, StatusCode) + Send>;
+pub(crate) type ErrorHandler = Box<dyn FnMut(&Error) + Send>;
+pub(crate) type ReconnectHandler = Box<dyn FnMut(u32, Duration) + Send>;
+
+/// State of a client's connection, like `EventSource.readyState` in browsers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadyState {
+    /// The client is connecting, or waiting before reconnecting.
+    Connecting,
+    /// The client is connected and receives events.
+    Open,
+    /// The client stopped and will not reconnect.
+    Closed,
+}
+
+/// Details about a connection of a client, see `Client::connection_info()`.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    /// Status code of the response.
+    pub status: StatusCode,
+    /// Headers of the response.
+    pub headers: HeaderMap,
+    /// URL of the response, after following redirects.
+    pub url: reqwest::Url,
+    /// Address of the server, if known.
+    pub remote_addr: Option<SocketAddr>,
+    /// Number of the reconnection attempt which opened the connection, 0 for the first one.
+    pub attempt: u32,
+    /// When the response arrived.
+    pub connected_at: SystemTime,
+    /// The `Last-Event-ID` header sent with the request.
+    pub last_event_id: Option<String>,
+}
+
 /// A client for a Server-Sent Events endpoint.
 ///
 /// Read events by iterating over the client.
+///
+/// Like browsers, the client reconnects to the URL it ended up at after following redirects, and
+/// stops reconnecting when the server responds with `204 No Content`.
 pub struct Client {
     client: reqw::Client,
-    response: Option<BufReader<reqw::Response>>,
+    response: Option<EventReader<BufReader<Box<dyn Read + Send>>>>,
     url: reqwest::Url,
-    last_event_id: Option<String>,
-    last_try: Option<Instant>,
+    core: ClientCore,
+    shared: Arc<Shared>,
+    interruptible: bool,
+    /// The current response is read by a `ThreadedReader`.
+    threaded: bool,
+
+    /// HTTP method of the requests, `GET` by default.
+    pub method: Method,
+
+    /// Request body, which is sent again on every reconnect.
+    pub body: Option<Vec<u8>>,
+
+    /// Additional request headers.
+    ///
+    /// Use `Client::on_request` for headers which change between reconnects.
+    pub headers: HeaderMap,
+
+    /// Whether to reconnect at all. Set this to `false` for one-shot streams: the iterator then
+    /// ends when the stream ends or fails.
+    pub reconnect: bool,
+
+    /// Maximum time without receiving any data, including comments.
+    ///
+    /// If the connection stays silent for longer, the client returns an `ErrorKind::IdleTimeout`
+    /// error and reconnects. With a timeout, the response is read on a separate thread.
+    ///
+    /// A blocking read can't be cancelled, so after a timeout the old connection and its thread
+    /// stay around until the server sends data or closes the connection. reqwest ends every read
+    /// of a response body after the `timeout` of the underlying `reqwest::blocking::Client`, 30
+    /// seconds by default, which bounds this. Without a reqwest timeout, a server which never
+    /// responds again keeps one thread and connection per idle timeout open.
+    pub idle_timeout: Option<Duration>,
 
     /// Reconnection time in milliseconds. Note that the reconnection time can be changed by the
     /// event stream, so changing this may not make a difference.
+    ///
+    /// The retry policy decides how this is used, see `Client::retry_policy`.
     pub retry: Duration,
+
+    /// Rules for giving up on reconnecting.
+    ///
+    /// When a rule triggers, the iterator returns a final `ErrorKind::GaveUp` error, which has the
+    /// last error as its cause. Afterwards, it returns `None`.
+    pub retry_limits: RetryLimits,
+
+    /// Options for parsing the event stream. Changes apply from the next connection on.
+    ///
+    /// If `warn_invalid_utf8` is set, lines with invalid UTF-8 are reported as
+    /// `ErrorKind::InvalidUtf8` errors. The connection stays open in that case.
+    ///
+    /// Exceeding one of the `limits` results in an `ErrorKind::LimitExceeded` error. With
+    /// `LimitPolicy::Fail`, the connection is closed and reopened after the retry time.
+    pub parser_options: ParserOptions,
 }
 
 impl Client {
@@ -64,60 +207,764 @@ impl Client {
         Client {
             client,
             response: None,
-            url: url,
-            last_event_id: None,
-            last_try: None,
+            url,
+            core: ClientCore::new(),
+            shared: Arc::new(Shared::default()),
+            interruptible: false,
+            threaded: false,
+            method: Method::GET,
+            body: None,
+            headers: HeaderMap::new(),
+            reconnect: true,
+            idle_timeout: None,
             retry: Duration::from_millis(DEFAULT_RETRY),
+            retry_limits: RetryLimits::default(),
+            parser_options: ParserOptions::default(),
+        }
+    }
+
+    /// Sets a function which is called for every comment line in the stream.
+    ///
+    /// Many servers regularly send comments to keep idle connections open, so this can be used to
+    /// check whether the connection is still alive.
+    pub fn on_comment<F>(&mut self, f: F)
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        self.core.on_comment = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called before every connection attempt.
+    ///
+    /// It can add headers to the request, e.g. an `Authorization` header with a token which
+    /// changes between reconnects.
+    pub fn on_request<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut HeaderMap) + Send + 'static,
+    {
+        self.core.on_request = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called when the server responds with `401 Unauthorized` or
+    /// `403 Forbidden`.
+    ///
+    /// This is the place to refresh credentials used by `on_request`. If the function returns
+    /// `true`, the client tries again after the usual delay. Otherwise, the error is fatal. The
+    /// function is called regardless of `Client::fatal_errors` and takes precedence over it.
+    pub fn on_auth_error<F>(&mut self, f: F)
+    where
+        F: FnMut(StatusCode) -> bool + Send + 'static,
+    {
+        self.core.on_auth_error = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called when a connection opens.
+    ///
+    /// It receives the number of the reconnection attempt, which is 0 for the first connection,
+    /// and the response status.
+    pub fn on_open<F>(&mut self, f: F)
+    where
+        F: FnMut(u32, StatusCode) + Send + 'static,
+    {
+        self.core.on_open = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called when an error ends a connection or a connection attempt.
+    ///
+    /// This happens before the client decides whether to reconnect. Errors which leave the
+    /// connection open, like `ErrorKind::InvalidUtf8`, are only returned by the iterator.
+    pub fn on_error<F>(&mut self, f: F)
+    where
+        F: FnMut(&Error) + Send + 'static,
+    {
+        self.core.on_error = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called when the client schedules a reconnect.
+    ///
+    /// It receives the number of the upcoming attempt, starting at 1 after every successful
+    /// connection, and the delay before it.
+    pub fn on_reconnect<F>(&mut self, f: F)
+    where
+        F: FnMut(u32, Duration) + Send + 'static,
+    {
+        self.core.on_reconnect = Some(Box::new(f));
+    }
+
+    /// Sets the policy deciding how long to wait before reconnecting.
+    ///
+    /// The default policy, `retry::Fixed`, always waits the reconnection time `retry`. If the
+    /// policy gives up, the iterator ends with an `ErrorKind::GaveUp` error.
+    pub fn retry_policy<P>(&mut self, policy: P)
+    where
+        P: RetryPolicy + 'static,
+    {
+        self.core.retry_state.policy = Box::new(policy);
+    }
+
+    /// Sets a function deciding whether to give up reconnecting after an error.
+    ///
+    /// If it returns `true`, the iterator ends with an `ErrorKind::GaveUp` error. It is only called
+    /// for retryable errors, see `Client::fatal_errors`.
+    pub fn give_up_on<F>(&mut self, f: F)
+    where
+        F: Fn(&Error) -> bool + Send + 'static,
+    {
+        self.core.retry_state.give_up_on = Some(Box::new(f));
+    }
+
+    /// Sets a function deciding which errors are fatal, replacing `Error::is_fatal`.
+    ///
+    /// The iterator returns a fatal error and ends afterwards. For example, this makes the client
+    /// retry when a gateway returns 502 during a deploy:
+    ///
+    /// ```no_run
+    /// # use eventsource::reqwest::{Client, ErrorKind};
+    /// # use reqwest::{StatusCode, Url};
+    /// # let mut client = Client::new(Url::parse("http://example.com").unwrap());
+    /// client.fatal_errors(|err| match err.kind() {
+    ///     ErrorKind::Http(StatusCode::BAD_GATEWAY) => false,
+    ///     _ => err.is_fatal(),
+    /// });
+    /// ```
+    pub fn fatal_errors<F>(&mut self, f: F)
+    where
+        F: Fn(&Error) -> bool + Send + 'static,
+    {
+        self.core.retry_state.is_fatal = Box::new(f);
+    }
+
+    /// Returns a handle which can close the client from another thread.
+    ///
+    /// From then on, responses are read on a separate thread, so that reading can be interrupted.
+    /// This includes the current response. Closing stops the client right away, but the
+    /// connection is only dropped once the read in progress returns, see `Client::idle_timeout`.
+    pub fn close_handle(&mut self) -> CloseHandle {
+        self.interruptible = true;
+        if let Some(ref mut response) = self.response {
+            if !self.threaded {
+                // Data already buffered stays in the `BufReader`.
+                let body = response.get_mut().get_mut();
+                let inner = mem::replace(body, Box::new(io::empty()));
+                let shared = self.shared.clone();
+                *body = Box::new(ThreadedReader::new(inner, shared, None));
+                self.threaded = true;
+            }
+        }
+        CloseHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Returns the ID of the last event, which is sent when reconnecting.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.core.last_event_id.as_deref()
+    }
+
+    /// Sets the ID sent with the next request, e.g. to resume a stream right after constructing
+    /// the client.
+    pub fn set_last_event_id(&mut self, id: Option<String>) {
+        self.core.last_event_id = id;
+    }
+
+    /// Sets a store which keeps the last event ID, e.g. across restarts.
+    ///
+    /// The saved ID is loaded right away. It replaces the current ID unless the store is empty.
+    /// Afterwards, the store is updated whenever the last event ID changes. If saving fails, the
+    /// iterator returns the error after the event, and the client keeps running.
+    pub fn last_event_id_store<S>(&mut self, store: S) -> io::Result<()>
+    where
+        S: LastEventIdStore + 'static,
+    {
+        self.core.set_store(store)
+    }
+
+    /// Returns details about the current connection, or the last one while reconnecting.
+    ///
+    /// This is `None` until the first connection opens.
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.core.connection.as_ref()
+    }
+
+    /// Returns a snapshot of the client's metrics.
+    pub fn stats(&self) -> ClientStats {
+        self.core.stats()
+    }
+
+    /// Returns the state of the connection.
+    pub fn ready_state(&self) -> ReadyState {
+        if self.core.closed || self.shared.is_closed() {
+            ReadyState::Closed
+        } else if self.response.is_some() {
+            ReadyState::Open
+        } else {
+            ReadyState::Connecting
         }
     }
 
     fn next_request(&mut self) -> Result<()> {
-        let mut headers = HeaderMap::with_capacity(2);
-        headers.insert(ACCEPT, HeaderValue::from_str("text/event-stream").unwrap());
-        if let Some(ref id) = self.last_event_id {
-            headers.insert("Last-Event-ID", HeaderValue::from_str(id).unwrap());
+        let (headers, last_event_id) = self.core.next_headers(&self.headers);
+        let mut request = self
+            .client
+            .request(self.method.clone(), self.url.clone())
+            .headers(headers);
+        if let Some(ref body) = self.body {
+            request = request.body(body.clone());
+        }
+        let res = request.send()?;
+        // The server tells us to stop reconnecting.
+        if res.status() == StatusCode::NO_CONTENT {
+            self.core.closed = true;
+            return Ok(());
         }
+        check_response(res.status(), res.headers())?;
+        self.core.opened(
+            res.status(),
+            res.headers(),
+            res.url(),
+            res.remote_addr(),
+            last_event_id,
+        );
+        // Reconnect to where redirects led us.
+        self.url = res.url().clone();
 
-        let res = self.client.get(self.url.clone()).headers(headers).send()?;
+        let res = self.core.metrics.count_bytes(res);
+        self.threaded = self.interruptible || self.idle_timeout.is_some();
+        let body: Box<dyn Read + Send> = if self.threaded {
+            let shared = self.shared.clone();
+            Box::new(ThreadedReader::new(res, shared, self.idle_timeout))
+        } else {
+            Box::new(res)
+        };
+        // Each connection starts a fresh stream, so a BOM may appear again.
+        let mut reader =
+            EventReader::with_options(BufReader::new(body), self.parser_options.clone());
+        reader
+            .parser_mut()
+            .set_last_event_id(self.core.last_event_id.clone());
+        self.response = Some(reader);
+        Ok(())
+    }
 
-        // Check status code and Content-Type.
-        {
-            let status = res.status();
-            if !status.is_success() {
-                return Err(ErrorKind::Http(status.clone()).into());
-            }
-
-            if let Some(content_type_hv) = res.headers().get(CONTENT_TYPE) {
-                let content_type = content_type_hv
-                    .to_str()
-                    .unwrap()
-                    .to_string()
-                    .parse::<mime::Mime>()
-                    .unwrap();
-                // Compare type and subtype only, MIME parameters are ignored.
-                if (content_type.type_(), content_type.subtype())
-                    != (mime::TEXT, mime::EVENT_STREAM)
-                {
-                    return Err(ErrorKind::InvalidContentType(content_type.clone()).into());
-                }
-            } else {
-                return Err(ErrorKind::NoContentType.into());
+    /// Drops the current connection.
+    fn close_response(&mut self) {
+        if self.response.take().is_some() {
+            self.core.metrics.disconnected();
+        }
+    }
+
+    /// Schedules the next attempt after `error` and returns the error to report.
+    fn fail(&mut self, error: Error) -> Error {
+        self.core
+            .fail(error, self.reconnect, self.retry, &self.retry_limits)
+    }
+
+    /// Closes the connection after `error` or EOF.
+    ///
+    /// Returns the final error if the client gives up.
+    fn disconnect(&mut self, error: Option<Error>) -> Option<Error> {
+        self.response = None;
+        self.core
+            .disconnect(error, self.reconnect, self.retry, &self.retry_limits)
+    }
+}
+
+/// A handle to close a `Client` from another thread.
+///
+/// Created by `Client::close_handle()`.
+#[derive(Clone)]
+pub struct CloseHandle {
+    shared: Arc<Shared>,
+}
+
+impl CloseHandle {
+    /// Closes the client.
+    ///
+    /// This interrupts the client while it waits before reconnecting or for data from the server.
+    /// Its iterator returns `None` from then on. A connection attempt in progress is not
+    /// interrupted, but the client stops once it completes.
+    pub fn close(&self) {
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_all();
+    }
+
+    /// Returns whether the client was closed.
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+}
+
+impl fmt::Debug for CloseHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CloseHandle")
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+/// State shared by a client, its close handles and the thread reading the response.
+#[derive(Default)]
+struct Shared {
+    state: Mutex<SharedState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct SharedState {
+    closed: bool,
+    /// Counts connections, so that reader threads of old connections stop.
+    connection: u64,
+    chunks: VecDeque<io::Result<Vec<u8>>>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, SharedState> {
+        self.state.lock().unwrap()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    /// Waits for `timeout` unless the client is closed in the meantime.
+    fn sleep(&self, timeout: Duration) {
+        let state = self.lock();
+        let _ = self
+            .changed
+            .wait_timeout_while(state, timeout, |state| !state.closed)
+            .unwrap();
+    }
+}
+
+/// Why `ThreadedReader` stopped reading.
+#[derive(Debug)]
+enum Interrupt {
+    Idle(Duration),
+    Closed,
+}
+
+impl fmt::Display for Interrupt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Interrupt::Idle(timeout) => write!(f, "no data received for {:?}", timeout),
+            Interrupt::Closed => write!(f, "client closed"),
+        }
+    }
+}
+
+impl StdError for Interrupt {}
+
+fn interrupt(err: &io::Error) -> Option<&Interrupt> {
+    err.get_ref()?.downcast_ref()
+}
+
+/// Number of chunks `ThreadedReader` reads ahead.
+const READ_AHEAD: usize = 4;
+
+/// Reads on a separate thread, so that reads can time out or be interrupted.
+///
+/// After an interruption, the thread exits with the next read on the inner reader, which drops
+/// the connection. Until then, it lingers; reqwest's read timeout bounds this.
+struct ThreadedReader {
+    shared: Arc<Shared>,
+    chunk: Cursor<Vec<u8>>,
+    done: bool,
+    idle_timeout: Option<Duration>,
+}
+
+impl ThreadedReader {
+    fn new<R>(mut inner: R, shared: Arc<Shared>, idle_timeout: Option<Duration>) -> ThreadedReader
+    where
+        R: Read + Send + 'static,
+    {
+        let connection = shared.lock().connection;
+        let thread_shared = shared.clone();
+        thread::spawn(move || loop {
+            let mut buf = vec![0; 8 * 1024];
+            let result = match inner.read(&mut buf) {
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                result => result.map(|n| {
+                    buf.truncate(n);
+                    buf
+                }),
+            };
+            let done = !matches!(result, Ok(ref buf) if !buf.is_empty());
+
+            let shared = &thread_shared;
+            let stale = |state: &SharedState| state.closed || state.connection != connection;
+            let mut state = shared
+                .changed
+                .wait_while(shared.lock(), |state| {
+                    state.chunks.len() >= READ_AHEAD && !stale(state)
+                })
+                .unwrap();
+            if stale(&state) {
+                break;
+            }
+            state.chunks.push_back(result);
+            shared.changed.notify_all();
+            if done {
+                break;
             }
+        });
+        ThreadedReader {
+            shared,
+            chunk: Cursor::new(Vec::new()),
+            done: false,
+            idle_timeout,
+        }
+    }
+
+    /// Waits for the next chunk from the reader thread.
+    fn next_chunk(&mut self) -> io::Result<Vec<u8>> {
+        let deadline = self
+            .idle_timeout
+            .map(|timeout| (Instant::now() + timeout, timeout));
+        let mut state = self.shared.lock();
+        loop {
+            if state.closed {
+                return Err(io::Error::other(Interrupt::Closed));
+            }
+            if let Some(chunk) = state.chunks.pop_front() {
+                self.shared.changed.notify_all();
+                return chunk;
+            }
+            state = match deadline {
+                Some((deadline, timeout)) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        let idle = Interrupt::Idle(timeout);
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, idle));
+                    }
+                    self.shared
+                        .changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0
+                }
+                None => self.shared.changed.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+impl Drop for ThreadedReader {
+    fn drop(&mut self) {
+        // Makes the reader thread stop.
+        let mut state = self.shared.lock();
+        state.connection += 1;
+        state.chunks.clear();
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Read for ThreadedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.done && self.chunk.position() as usize == self.chunk.get_ref().len() {
+            let chunk = self.next_chunk();
+            // The reader thread stops after EOF and errors.
+            self.done = !matches!(chunk, Ok(ref chunk) if !chunk.is_empty());
+            self.chunk = Cursor::new(chunk?);
+        }
+        self.chunk.read(buf)
+    }
+}
+
+/// State and behavior shared by the blocking and the async client.
+///
+/// The clients only own their connection and the public settings, which are passed in where
+/// needed.
+pub(crate) struct ClientCore {
+    pub(crate) last_event_id: Option<String>,
+    /// Time of the last connection attempt or disconnect.
+    pub(crate) last_try: Option<Instant>,
+    pub(crate) on_comment: Option<CommentHandler>,
+    pub(crate) on_request: Option<RequestHandler>,
+    pub(crate) on_auth_error: Option<AuthErrorHandler>,
+    pub(crate) on_open: Option<OpenHandler>,
+    pub(crate) on_error: Option<ErrorHandler>,
+    pub(crate) on_reconnect: Option<ReconnectHandler>,
+    pub(crate) retry_state: RetryState,
+    pub(crate) store: Option<Box<dyn LastEventIdStore>>,
+    /// Error from saving to the store, returned before the next item.
+    pub(crate) store_error: Option<Error>,
+    pub(crate) connection: Option<ConnectionInfo>,
+    pub(crate) metrics: Metrics,
+    /// Delay before the next attempt, as decided by the retry policy.
+    pub(crate) delay: Duration,
+    /// Whether the client stopped for good.
+    pub(crate) closed: bool,
+}
+
+impl ClientCore {
+    pub(crate) fn new() -> ClientCore {
+        ClientCore {
+            last_event_id: None,
+            last_try: None,
+            on_comment: None,
+            on_request: None,
+            on_auth_error: None,
+            on_open: None,
+            on_error: None,
+            on_reconnect: None,
+            retry_state: RetryState::new(),
+            store: None,
+            store_error: None,
+            connection: None,
+            metrics: Metrics::default(),
+            delay: Duration::from_millis(DEFAULT_RETRY),
+            closed: false,
         }
+    }
 
-        self.response = Some(BufReader::new(res));
+    /// Loads the last event ID from `store` and keeps the store for saving it.
+    pub(crate) fn set_store<S>(&mut self, mut store: S) -> io::Result<()>
+    where
+        S: LastEventIdStore + 'static,
+    {
+        if let Some(id) = store.load()? {
+            self.last_event_id = Some(id);
+        }
+        self.store = Some(Box::new(store));
         Ok(())
     }
+
+    pub(crate) fn stats(&self) -> ClientStats {
+        self.metrics.snapshot(self.delay)
+    }
+
+    /// Builds the headers of the next request, returning them with the `Last-Event-ID` sent.
+    pub(crate) fn next_headers(&mut self, extra: &HeaderMap) -> (HeaderMap, Option<String>) {
+        let mut headers = request_headers(self.last_event_id.as_deref());
+        headers.extend(extra.clone());
+        if let Some(ref mut on_request) = self.on_request {
+            on_request(&mut headers);
+        }
+        let last_event_id = sent_last_event_id(&headers);
+        (headers, last_event_id)
+    }
+
+    /// Records a successful response, which starts a connection.
+    pub(crate) fn opened(
+        &mut self,
+        status: StatusCode,
+        headers: &HeaderMap,
+        url: &reqwest::Url,
+        remote_addr: Option<SocketAddr>,
+        last_event_id: Option<String>,
+    ) {
+        if let Some(ref mut on_open) = self.on_open {
+            on_open(self.retry_state.attempt(), status);
+        }
+        self.connection = Some(ConnectionInfo {
+            status,
+            headers: headers.clone(),
+            url: url.clone(),
+            remote_addr,
+            attempt: self.retry_state.attempt(),
+            connected_at: SystemTime::now(),
+            last_event_id,
+        });
+        self.retry_state.connected();
+        self.metrics.connected();
+    }
+
+    pub(crate) fn comment(&mut self, text: &str) {
+        if let Some(ref mut on_comment) = self.on_comment {
+            on_comment(text);
+        }
+    }
+
+    /// Remembers the last event ID after the parser returned `event` or another item, and saves
+    /// it in the store.
+    pub(crate) fn update_last_event_id(
+        &mut self,
+        dispatch_mode: DispatchMode,
+        parser: &EventStreamParser,
+        event: Option<&Event>,
+    ) {
+        let id = match dispatch_mode {
+            DispatchMode::Legacy => match event.and_then(|event| event.id.as_ref()) {
+                Some(id) => Some(id.as_str()),
+                None => return,
+            },
+            // Blocks without data change the ID as well, so ask the parser. Only ended blocks
+            // count, so that an event cut off by the end of the connection is sent again.
+            DispatchMode::Whatwg => parser.committed_last_event_id(),
+        };
+        if id == self.last_event_id.as_deref() {
+            return;
+        }
+        self.last_event_id = id.map(str::to_string);
+        if let Some(ref mut store) = self.store {
+            if let Err(err) = store.save(self.last_event_id.as_deref()) {
+                self.store_error = Some(err.into());
+            }
+        }
+    }
+
+    /// Decides when to reconnect after a failed attempt or a closed connection.
+    ///
+    /// Returns the reason if the client gives up.
+    fn schedule_retry(
+        &mut self,
+        error: Option<&Error>,
+        reconnect: bool,
+        retry: Duration,
+        limits: &RetryLimits,
+    ) -> Option<GiveUpReason> {
+        if !reconnect {
+            self.closed = true;
+            return None;
+        }
+        match self.retry_state.next_delay(error, retry, limits) {
+            Ok(delay) => {
+                self.delay = delay;
+                self.metrics.reconnect();
+                if let Some(ref mut on_reconnect) = self.on_reconnect {
+                    on_reconnect(self.retry_state.attempt(), delay);
+                }
+                None
+            }
+            Err(reason) => {
+                self.closed = true;
+                Some(reason)
+            }
+        }
+    }
+
+    /// Counts an error which does not affect the connection and returns it.
+    pub(crate) fn report(&mut self, error: Error) -> Error {
+        self.metrics.error(&error);
+        error
+    }
+
+    /// Builds the final error after giving up, with the last error as cause.
+    fn give_up(&mut self, reason: GiveUpReason, error: Option<Error>) -> Error {
+        let error = match error {
+            Some(error) => Error::with_chain(error, ErrorKind::GaveUp(reason)),
+            None => ErrorKind::GaveUp(reason).into(),
+        };
+        self.report(error)
+    }
+
+    /// Schedules the next attempt after `error` and returns the error to report.
+    pub(crate) fn fail(
+        &mut self,
+        error: Error,
+        reconnect: bool,
+        retry: Duration,
+        limits: &RetryLimits,
+    ) -> Error {
+        self.metrics.error(&error);
+        if let Some(ref mut on_error) = self.on_error {
+            on_error(&error);
+        }
+        // The `on_auth_error` handler overrides the fatal errors predicate.
+        let fatal = match refresh_credentials(&mut self.on_auth_error, &error) {
+            Some(retry) => !retry,
+            None => self.retry_state.is_fatal(&error),
+        };
+        if fatal {
+            self.closed = true;
+            return error;
+        }
+        match self.schedule_retry(Some(&error), reconnect, retry, limits) {
+            Some(reason) => self.give_up(reason, Some(error)),
+            None => error,
+        }
+    }
+
+    /// Records that the client dropped its connection.
+    pub(crate) fn disconnected(&mut self) {
+        self.last_try = Some(Instant::now());
+        self.metrics.disconnected();
+    }
+
+    /// Handles the end of a connection after `error` or EOF.
+    ///
+    /// Returns the final error if the client gives up.
+    pub(crate) fn disconnect(
+        &mut self,
+        error: Option<Error>,
+        reconnect: bool,
+        retry: Duration,
+        limits: &RetryLimits,
+    ) -> Option<Error> {
+        self.disconnected();
+        match error {
+            Some(error) => {
+                let error = self.fail(error, reconnect, retry, limits);
+                if self.closed {
+                    Some(error)
+                } else {
+                    None
+                }
+            }
+            None => self
+                .schedule_retry(None, reconnect, retry, limits)
+                .map(|reason| self.give_up(reason, None)),
+        }
+    }
+}
+
+/// Calls the `on_auth_error` handler for authentication errors.
+///
+/// Returns whether the client should try again, or `None` without a handler or for other errors.
+fn refresh_credentials(handler: &mut Option<AuthErrorHandler>, error: &Error) -> Option<bool> {
+    match (error.kind(), handler) {
+        (&ErrorKind::Http(status), Some(handler))
+            if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN =>
+        {
+            Some(handler(status))
+        }
+        _ => None,
+    }
 }
 
-// Helper macro for Option<Result<...>>
-macro_rules! try_option {
-    ($e:expr) => {
-        match $e {
-            Ok(val) => val,
-            Err(err) => return Some(Err(::std::convert::From::from(err))),
+/// Builds the headers for a request continuing after `last_event_id`.
+fn request_headers(last_event_id: Option<&str>) -> HeaderMap {
+    let mut headers = HeaderMap::with_capacity(3);
+    headers.insert(ACCEPT, HeaderValue::from_static("text/event-stream"));
+    // Proxies must not answer from their cache.
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    // Non-ASCII IDs are sent as UTF-8 like browsers do. IDs with control characters can't be
+    // sent at all, so the server sees a fresh connection instead.
+    if let Some(value) = last_event_id.and_then(|id| HeaderValue::from_bytes(id.as_bytes()).ok()) {
+        headers.insert("Last-Event-ID", value);
+    }
+    headers
+}
+
+/// Returns the `Last-Event-ID` header of a request.
+fn sent_last_event_id(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get("Last-Event-ID")?;
+    Some(String::from_utf8_lossy(value.as_bytes()).into_owned())
+}
+
+/// Checks status code and Content-Type of a response.
+pub(crate) fn check_response(status: StatusCode, headers: &HeaderMap) -> Result<()> {
+    if !status.is_success() {
+        return Err(ErrorKind::Http(status).into());
+    }
+
+    if let Some(content_type_hv) = headers.get(CONTENT_TYPE) {
+        let content_type = content_type_hv
+            .to_str()
+            .ok()
+            .and_then(|value| value.parse::<mime::Mime>().ok())
+            .ok_or_else(|| {
+                let value = String::from_utf8_lossy(content_type_hv.as_bytes());
+                ErrorKind::MalformedContentType(value.into_owned())
+            })?;
+        // Compare type and subtype only, MIME parameters are ignored.
+        if (content_type.type_(), content_type.subtype()) != (mime::TEXT, mime::EVENT_STREAM) {
+            return Err(ErrorKind::InvalidContentType(content_type.clone()).into());
         }
-    };
+    } else {
+        return Err(ErrorKind::NoContentType.into());
+    }
+    Ok(())
 }
 
 /// Iterate over the client to get events.
@@ -127,58 +974,96 @@ impl Iterator for Client {
     type Item = Result<Event>;
 
     fn next(&mut self) -> Option<Result<Event>> {
-        if self.response.is_none() {
-            // We may have to wait for the next request.
-            if let Some(last_try) = self.last_try {
-                let elapsed = last_try.elapsed();
-                if elapsed < self.retry {
-                    ::std::thread::sleep(self.retry - elapsed);
-                }
+        let item = self.next_item();
+        if let Some(Ok(ref event)) = item {
+            self.core.metrics.event(event);
+        }
+        item
+    }
+}
+
+impl Client {
+    /// Reads from the connection, reconnecting as needed, until there is an event or an error.
+    fn next_item(&mut self) -> Option<Result<Event>> {
+        if let Some(err) = self.core.store_error.take() {
+            return Some(Err(self.core.report(err)));
+        }
+        loop {
+            if self.core.closed || self.shared.is_closed() {
+                self.core.closed = true;
+                self.close_response();
+                return None;
             }
-            // Set here in case the request fails.
-            self.last_try = Some(Instant::now());
-
-            try_option!(self.next_request());
-        }
-
-        let result = {
-            let mut event = Event::new();
-            let mut line = String::new();
-            let reader = self.response.as_mut().unwrap();
-
-            loop {
-                match reader.read_line(&mut line) {
-                    // Got new bytes from stream
-                    Ok(_n) if _n > 0 => {
-                        match parse_event_line(&line, &mut event) {
-                            ParseResult::Next => (), // okay, just continue
-                            ParseResult::Dispatch => {
-                                if let Some(ref id) = event.id {
-                                    self.last_event_id = Some(id.clone());
-                                }
-                                return Some(Ok(event));
-                            }
-                            ParseResult::SetRetry(ref retry) => {
-                                self.retry = *retry;
-                            }
-                        }
-                        line.clear();
+            if self.response.is_none() {
+                // We may have to wait for the next request.
+                if let Some(last_try) = self.core.last_try {
+                    let elapsed = last_try.elapsed();
+                    if elapsed < self.core.delay {
+                        self.shared.sleep(self.core.delay - elapsed);
+                        continue;
                     }
-                    // Nothing read from stream
-                    Ok(_) => break None,
-                    Err(err) => break Some(Err(::std::convert::From::from(err))),
+                }
+                // Set here in case the request fails.
+                self.core.last_try = Some(Instant::now());
+
+                if let Err(err) = self.next_request() {
+                    return Some(Err(self.fail(err)));
+                }
+                if self.core.closed {
+                    return None;
                 }
             }
-        };
 
-        match result {
-            None | Some(Err(_)) => {
+            let response = self.response.as_mut().unwrap();
+            let item = response.next();
+            let event = match item {
+                Some(Ok(StreamItem::Event(ref event))) => Some(event),
+                _ => None,
+            };
+            let dispatch_mode = self.parser_options.dispatch_mode;
+            self.core
+                .update_last_event_id(dispatch_mode, response.parser(), event);
+
+            match item {
+                Some(Ok(StreamItem::Event(event))) => return Some(Ok(event)),
+                Some(Ok(StreamItem::Retry(retry))) => self.retry = retry,
+                Some(Ok(StreamItem::Comment(text))) => self.core.comment(&text),
+                Some(Ok(StreamItem::InvalidUtf8(line))) => {
+                    let err = ErrorKind::InvalidUtf8(line).into();
+                    return Some(Err(self.core.report(err)));
+                }
+                Some(Ok(StreamItem::LimitExceeded(limit))) => {
+                    let err = ErrorKind::LimitExceeded(limit).into();
+                    if self.parser_options.limits.policy != LimitPolicy::Fail {
+                        return Some(Err(self.core.report(err)));
+                    }
+                    // Counted when closing the connection.
+                    if let Some(err) = self.disconnect(Some(err)) {
+                        return Some(Err(err));
+                    }
+                    return Some(Err(ErrorKind::LimitExceeded(limit).into()));
+                }
                 // EOF or a stream error, retry after timeout
-                self.last_try = Some(Instant::now());
-                self.response = None;
-                self.next()
+                None => {
+                    if let Some(err) = self.disconnect(None) {
+                        return Some(Err(err));
+                    }
+                }
+                Some(Err(err)) => match interrupt(&err) {
+                    Some(&Interrupt::Closed) => continue,
+                    // Nothing arrived in time, report it and reconnect.
+                    Some(&Interrupt::Idle(timeout)) => {
+                        self.response = None;
+                        self.core.disconnected();
+                        return Some(Err(self.fail(ErrorKind::IdleTimeout(timeout).into())));
+                    }
+                    None => {
+                        if let Some(err) = self.disconnect(Some(err.into())) {
+                            return Some(Err(err));
+                        }
+                    }
+                },
             }
-            _ => result,
         }
     }
 }
@@ -0,0 +1,470 @@
+//! # Async reqwest-based EventSource client
+//!
+//! Requires the `with-reqwest-async` feature. The client has to run on a tokio 0.2 runtime, like
+//! reqwest itself.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use eventsource::reqwest_async::Client;
+//! use futures_util::stream::StreamExt;
+//! use reqwest::Url;
+//!
+//! # async fn run() {
+//! let mut client = Client::new(Url::parse("http://example.com").unwrap());
+//! while let Some(event) = client.next().await {
+//!     println!("{}", event.unwrap());
+//! }
+//! # }
+//! ```
+
+pub use super::reqwest::{ConnectionInfo, Error, ErrorKind, ReadyState, Result};
+
+use super::event::{Event, EventStreamParser, LimitPolicy, ParserOptions, StreamItem};
+use super::reqwest::{check_response, ClientCore};
+use super::retry::{RetryLimits, RetryPolicy};
+use super::stats::ClientStats;
+use super::store::LastEventIdStore;
+use bytes::Bytes;
+use futures_core::{ready, Stream};
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::time::{delay_for, Delay};
+
+const DEFAULT_RETRY: u64 = 5000;
+
+type ResponseFuture = Pin<Box<dyn Future<Output = reqwest::Result<reqwest::Response>> + Send>>;
+type BodyStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+/// An async client for a Server-Sent Events endpoint.
+///
+/// Read events by polling the client as a `Stream`. It behaves like the blocking
+/// `reqwest::Client`, but waits for reconnections with tokio's timer instead of blocking the
+/// thread.
+pub struct Client {
+    client: reqwest::Client,
+    state: State,
+    parser: EventStreamParser,
+    url: reqwest::Url,
+    core: ClientCore,
+
+    /// HTTP method of the requests, `GET` by default.
+    pub method: Method,
+
+    /// Request body, which is sent again on every reconnect.
+    pub body: Option<Vec<u8>>,
+
+    /// Additional request headers.
+    ///
+    /// Use `Client::on_request` for headers which change between reconnects.
+    pub headers: HeaderMap,
+
+    /// Whether to reconnect at all.
+    ///
+    /// See `reqwest::Client::reconnect`.
+    pub reconnect: bool,
+
+    /// Maximum time without receiving any data, including comments.
+    ///
+    /// See `reqwest::Client::idle_timeout`.
+    pub idle_timeout: Option<Duration>,
+
+    /// Reconnection time in milliseconds. Note that the reconnection time can be changed by the
+    /// event stream, so changing this may not make a difference.
+    ///
+    /// The retry policy decides how this is used, see `Client::retry_policy`.
+    pub retry: Duration,
+
+    /// Rules for giving up on reconnecting.
+    ///
+    /// See `reqwest::Client::retry_limits`.
+    pub retry_limits: RetryLimits,
+
+    /// Options for parsing the event stream. Changes apply from the next connection on.
+    ///
+    /// See the blocking `reqwest::Client::parser_options` for how parser errors are reported.
+    pub parser_options: ParserOptions,
+}
+
+enum State {
+    /// Not connected, a request has to be made.
+    Idle,
+    /// Waiting for the retry time to pass before reconnecting.
+    Waiting(Pin<Box<Delay>>),
+    /// Waiting for the response to a request with the given `Last-Event-ID`.
+    Connecting(ResponseFuture, Option<String>),
+    Streaming(BodyStream, Option<IdleTimer>),
+}
+
+/// Fires when no data arrived for `timeout`.
+struct IdleTimer {
+    timeout: Duration,
+    delay: Delay,
+}
+
+impl IdleTimer {
+    fn new(timeout: Duration) -> IdleTimer {
+        IdleTimer {
+            timeout,
+            delay: delay_for(timeout),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.delay.reset(tokio::time::Instant::now() + self.timeout);
+    }
+}
+
+impl Client {
+    /// Constructs a new EventSource client for the given URL.
+    ///
+    /// This does not start an HTTP request.
+    pub fn new(url: reqwest::Url) -> Client {
+        Self::new_with_client(url, reqwest::Client::new())
+    }
+
+    /// Constructs a new EventSource client for the given URL and reqwest Client.
+    ///
+    /// This does not start an HTTP request.
+    pub fn new_with_client(url: reqwest::Url, client: reqwest::Client) -> Client {
+        Client {
+            client,
+            state: State::Idle,
+            parser: EventStreamParser::new(),
+            url,
+            core: ClientCore::new(),
+            method: Method::GET,
+            body: None,
+            headers: HeaderMap::new(),
+            reconnect: true,
+            idle_timeout: None,
+            retry: Duration::from_millis(DEFAULT_RETRY),
+            retry_limits: RetryLimits::default(),
+            parser_options: ParserOptions::default(),
+        }
+    }
+
+    /// Sets a function which is called for every comment line in the stream.
+    ///
+    /// See `reqwest::Client::on_comment`.
+    pub fn on_comment<F>(&mut self, f: F)
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        self.core.on_comment = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called before every connection attempt.
+    ///
+    /// See `reqwest::Client::on_request`.
+    pub fn on_request<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut HeaderMap) + Send + 'static,
+    {
+        self.core.on_request = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called when the server responds with `401 Unauthorized` or
+    /// `403 Forbidden`.
+    ///
+    /// See `reqwest::Client::on_auth_error`.
+    pub fn on_auth_error<F>(&mut self, f: F)
+    where
+        F: FnMut(StatusCode) -> bool + Send + 'static,
+    {
+        self.core.on_auth_error = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called when a connection opens.
+    ///
+    /// See `reqwest::Client::on_open`.
+    pub fn on_open<F>(&mut self, f: F)
+    where
+        F: FnMut(u32, StatusCode) + Send + 'static,
+    {
+        self.core.on_open = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called when an error ends a connection or a connection attempt.
+    ///
+    /// See `reqwest::Client::on_error`.
+    pub fn on_error<F>(&mut self, f: F)
+    where
+        F: FnMut(&Error) + Send + 'static,
+    {
+        self.core.on_error = Some(Box::new(f));
+    }
+
+    /// Sets a function which is called when the client schedules a reconnect.
+    ///
+    /// See `reqwest::Client::on_reconnect`.
+    pub fn on_reconnect<F>(&mut self, f: F)
+    where
+        F: FnMut(u32, Duration) + Send + 'static,
+    {
+        self.core.on_reconnect = Some(Box::new(f));
+    }
+
+    /// Sets the policy deciding how long to wait before reconnecting.
+    ///
+    /// See `reqwest::Client::retry_policy`.
+    pub fn retry_policy<P>(&mut self, policy: P)
+    where
+        P: RetryPolicy + 'static,
+    {
+        self.core.retry_state.policy = Box::new(policy);
+    }
+
+    /// Sets a function deciding whether to give up reconnecting after an error.
+    ///
+    /// See `reqwest::Client::give_up_on`.
+    pub fn give_up_on<F>(&mut self, f: F)
+    where
+        F: Fn(&Error) -> bool + Send + 'static,
+    {
+        self.core.retry_state.give_up_on = Some(Box::new(f));
+    }
+
+    /// Sets a function deciding which errors are fatal, replacing `Error::is_fatal`.
+    ///
+    /// See `reqwest::Client::fatal_errors`.
+    pub fn fatal_errors<F>(&mut self, f: F)
+    where
+        F: Fn(&Error) -> bool + Send + 'static,
+    {
+        self.core.retry_state.is_fatal = Box::new(f);
+    }
+
+    /// Returns the ID of the last event, which is sent when reconnecting.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.core.last_event_id.as_deref()
+    }
+
+    /// Sets the ID sent with the next request.
+    ///
+    /// See `reqwest::Client::set_last_event_id`.
+    pub fn set_last_event_id(&mut self, id: Option<String>) {
+        self.core.last_event_id = id;
+    }
+
+    /// Sets a store which keeps the last event ID, e.g. across restarts.
+    ///
+    /// See `reqwest::Client::last_event_id_store`. Saving blocks the thread, so the store should
+    /// be fast.
+    pub fn last_event_id_store<S>(&mut self, store: S) -> io::Result<()>
+    where
+        S: LastEventIdStore + 'static,
+    {
+        self.core.set_store(store)
+    }
+
+    /// Returns details about the current connection, or the last one while reconnecting.
+    ///
+    /// This is `None` until the first connection opens.
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.core.connection.as_ref()
+    }
+
+    /// Returns a snapshot of the client's metrics.
+    pub fn stats(&self) -> ClientStats {
+        self.core.stats()
+    }
+
+    /// Returns the state of the connection.
+    pub fn ready_state(&self) -> ReadyState {
+        if self.core.closed {
+            return ReadyState::Closed;
+        }
+        match self.state {
+            State::Streaming(..) => ReadyState::Open,
+            State::Idle | State::Waiting(_) | State::Connecting(..) => ReadyState::Connecting,
+        }
+    }
+
+    fn start_request(&mut self) {
+        // Set here in case the request fails.
+        self.core.last_try = Some(Instant::now());
+        let (headers, last_event_id) = self.core.next_headers(&self.headers);
+        let mut request = self
+            .client
+            .request(self.method.clone(), self.url.clone())
+            .headers(headers);
+        if let Some(ref body) = self.body {
+            request = request.body(body.clone());
+        }
+        let request = request.send();
+        self.state = State::Connecting(Box::pin(request), last_event_id);
+    }
+
+    fn start_streaming(
+        &mut self,
+        res: reqwest::Response,
+        last_event_id: Option<String>,
+    ) -> Result<()> {
+        // The server tells us to stop reconnecting.
+        if res.status() == StatusCode::NO_CONTENT {
+            self.core.closed = true;
+            return Ok(());
+        }
+        check_response(res.status(), res.headers())?;
+        self.core.opened(
+            res.status(),
+            res.headers(),
+            res.url(),
+            res.remote_addr(),
+            last_event_id,
+        );
+        // Reconnect to where redirects led us.
+        self.url = res.url().clone();
+        // Each connection starts a fresh stream, so a BOM may appear again.
+        self.parser = EventStreamParser::with_options(self.parser_options.clone());
+        self.parser
+            .set_last_event_id(self.core.last_event_id.clone());
+        let idle = self.idle_timeout.map(IdleTimer::new);
+        self.state = State::Streaming(Box::pin(res.bytes_stream()), idle);
+        Ok(())
+    }
+
+    /// Schedules the next attempt after `error` and returns the error to report.
+    fn fail(&mut self, error: Error) -> Error {
+        self.core
+            .fail(error, self.reconnect, self.retry, &self.retry_limits)
+    }
+
+    /// Closes the connection after `error` or EOF.
+    ///
+    /// Returns the final error if the client gives up.
+    fn disconnect(&mut self, error: Option<Error>) -> Option<Error> {
+        self.state = State::Idle;
+        self.core
+            .disconnect(error, self.reconnect, self.retry, &self.retry_limits)
+    }
+}
+
+/// Poll the client to get events.
+///
+/// HTTP requests are made transparently while polling.
+impl Stream for Client {
+    type Item = Result<Event>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Event>>> {
+        let item = ready!(self.as_mut().poll_item(cx));
+        if let Some(Ok(ref event)) = item {
+            self.core.metrics.event(event);
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl Client {
+    /// Polls the connection, reconnecting as needed, until there is an event or an error.
+    fn poll_item(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Event>>> {
+        let this = &mut *self;
+        if let Some(err) = this.core.store_error.take() {
+            return Poll::Ready(Some(Err(this.core.report(err))));
+        }
+        loop {
+            if this.core.closed {
+                return Poll::Ready(None);
+            }
+            match this.state {
+                State::Idle => {
+                    // We may have to wait for the next request.
+                    if let Some(last_try) = this.core.last_try {
+                        let elapsed = last_try.elapsed();
+                        if elapsed < this.core.delay {
+                            let delay = this.core.delay - elapsed;
+                            this.state = State::Waiting(Box::pin(delay_for(delay)));
+                            continue;
+                        }
+                    }
+                    this.start_request();
+                }
+                State::Waiting(ref mut delay) => {
+                    ready!(delay.as_mut().poll(cx));
+                    this.start_request();
+                }
+                State::Connecting(ref mut request, ref mut last_event_id) => {
+                    let result = ready!(request.as_mut().poll(cx));
+                    let last_event_id = last_event_id.take();
+                    this.state = State::Idle;
+                    if let Err(err) = result
+                        .map_err(Error::from)
+                        .and_then(|res| this.start_streaming(res, last_event_id))
+                    {
+                        return Poll::Ready(Some(Err(this.fail(err))));
+                    }
+                }
+                State::Streaming(ref mut body, ref mut idle) => {
+                    let item = this.parser.next_item();
+                    let event = match item {
+                        Some(StreamItem::Event(ref event)) => Some(event),
+                        _ => None,
+                    };
+                    let dispatch_mode = this.parser_options.dispatch_mode;
+                    this.core
+                        .update_last_event_id(dispatch_mode, &this.parser, event);
+                    match item {
+                        Some(StreamItem::Event(event)) => return Poll::Ready(Some(Ok(event))),
+                        Some(StreamItem::Retry(retry)) => this.retry = retry,
+                        Some(StreamItem::Comment(text)) => this.core.comment(&text),
+                        Some(StreamItem::InvalidUtf8(line)) => {
+                            let err = ErrorKind::InvalidUtf8(line).into();
+                            return Poll::Ready(Some(Err(this.core.report(err))));
+                        }
+                        Some(StreamItem::LimitExceeded(limit)) => {
+                            let err = ErrorKind::LimitExceeded(limit).into();
+                            if this.parser_options.limits.policy != LimitPolicy::Fail {
+                                return Poll::Ready(Some(Err(this.core.report(err))));
+                            }
+                            // Counted when closing the connection.
+                            if let Some(err) = this.disconnect(Some(err)) {
+                                return Poll::Ready(Some(Err(err)));
+                            }
+                            return Poll::Ready(Some(Err(ErrorKind::LimitExceeded(limit).into())));
+                        }
+                        None => match body.as_mut().poll_next(cx) {
+                            Poll::Ready(Some(Ok(chunk))) => {
+                                this.core.metrics.bytes_read(chunk.len());
+                                this.parser.feed(&chunk);
+                                if let Some(ref mut idle) = *idle {
+                                    idle.reset();
+                                }
+                            }
+                            // EOF or a stream error, retry after timeout
+                            Poll::Ready(Some(Err(err))) => {
+                                if let Some(err) = this.disconnect(Some(err.into())) {
+                                    return Poll::Ready(Some(Err(err)));
+                                }
+                            }
+                            Poll::Ready(None) => {
+                                if let Some(err) = this.disconnect(None) {
+                                    return Poll::Ready(Some(Err(err)));
+                                }
+                            }
+                            // Nothing arrived in time, report it and reconnect.
+                            Poll::Pending => {
+                                let timeout = match *idle {
+                                    Some(ref mut idle) => {
+                                        ready!(Pin::new(&mut idle.delay).poll(cx));
+                                        idle.timeout
+                                    }
+                                    None => return Poll::Pending,
+                                };
+                                this.state = State::Idle;
+                                this.core.disconnected();
+                                let err = ErrorKind::IdleTimeout(timeout).into();
+                                return Poll::Ready(Some(Err(this.fail(err))));
+                            }
+                        },
+                    }
+                }
+            }
+        }
+    }
+}
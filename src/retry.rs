@@ -0,0 +1,370 @@
+//! # Reconnection policies
+//!
+//! A `RetryPolicy` decides how long a client waits before reconnecting, or whether it gives up.
+//! By default, clients wait the reconnection time before every attempt, like browsers do. When
+//! many clients lose their connection at the same moment, e.g. because the server restarted,
+//! `Exponential` or `DecorrelatedJitter` avoid having all of them reconnect at once.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use eventsource::reqwest::Client;
+//! use eventsource::retry::DecorrelatedJitter;
+//! use reqwest::Url;
+//! use std::time::Duration;
+//!
+//! let mut client = Client::new(Url::parse("http://example.com").unwrap());
+//! client.retry_policy(DecorrelatedJitter::new(Duration::from_secs(60)));
+//! ```
+
+use super::reqwest::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub(crate) type ErrorPredicate = Box<dyn Fn(&Error) -> bool + Send>;
+
+/// Decides when a client reconnects.
+///
+/// Closures with the same signature as `next_delay` implement this trait as well.
+pub trait RetryPolicy: Send {
+    /// Returns how long to wait before reconnection attempt number `attempt`, or `None` to give
+    /// up.
+    ///
+    /// `attempt` counts the attempts since the last successful connection, starting at 1. `error`
+    /// is the error which ended the previous connection or attempt. It is `None` if the server
+    /// closed the stream. `retry` is the client's reconnection time, which the server may have
+    /// changed with a `retry` field.
+    fn next_delay(
+        &mut self,
+        attempt: u32,
+        error: Option<&Error>,
+        retry: Duration,
+    ) -> Option<Duration>;
+}
+
+impl<F> RetryPolicy for F
+where
+    F: FnMut(u32, Option<&Error>, Duration) -> Option<Duration> + Send,
+{
+    fn next_delay(
+        &mut self,
+        attempt: u32,
+        error: Option<&Error>,
+        retry: Duration,
+    ) -> Option<Duration> {
+        self(attempt, error, retry)
+    }
+}
+
+/// Waits the reconnection time before every attempt.
+///
+/// This is the default policy.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fixed;
+
+impl RetryPolicy for Fixed {
+    fn next_delay(&mut self, _: u32, _: Option<&Error>, retry: Duration) -> Option<Duration> {
+        Some(retry)
+    }
+}
+
+/// Multiplies the delay by `factor` after every failed attempt.
+///
+/// The first attempt waits the reconnection time. No delay is longer than `max`.
+#[derive(Debug, Clone, Copy)]
+pub struct Exponential {
+    /// Factor the delay grows by with every attempt. Values below 1 are treated as 1, which
+    /// keeps the delay at the reconnection time.
+    pub factor: u32,
+    /// Upper bound of the delay.
+    pub max: Duration,
+}
+
+impl Exponential {
+    /// Creates a policy doubling the delay up to `max`.
+    pub fn new(max: Duration) -> Exponential {
+        Exponential { factor: 2, max }
+    }
+}
+
+impl Default for Exponential {
+    fn default() -> Exponential {
+        Exponential::new(Duration::from_secs(60))
+    }
+}
+
+impl RetryPolicy for Exponential {
+    fn next_delay(&mut self, attempt: u32, _: Option<&Error>, retry: Duration) -> Option<Duration> {
+        let delay = self
+            .factor
+            .max(1)
+            .checked_pow(attempt.saturating_sub(1))
+            .and_then(|factor| retry.checked_mul(factor))
+            .unwrap_or(self.max);
+        Some(delay.min(self.max))
+    }
+}
+
+/// Picks a random delay between the reconnection time and three times the previous delay.
+///
+/// This is the "decorrelated jitter" algorithm, which spreads out reconnecting clients while
+/// still backing off. No delay is longer than `max`.
+#[derive(Debug, Clone)]
+pub struct DecorrelatedJitter {
+    /// Upper bound of the delay. It wins over the reconnection time if that is larger.
+    pub max: Duration,
+    previous: Option<Duration>,
+}
+
+impl DecorrelatedJitter {
+    /// Creates a policy with delays up to `max`.
+    pub fn new(max: Duration) -> DecorrelatedJitter {
+        DecorrelatedJitter {
+            max,
+            previous: None,
+        }
+    }
+}
+
+impl Default for DecorrelatedJitter {
+    fn default() -> DecorrelatedJitter {
+        DecorrelatedJitter::new(Duration::from_secs(60))
+    }
+}
+
+impl RetryPolicy for DecorrelatedJitter {
+    fn next_delay(&mut self, attempt: u32, _: Option<&Error>, retry: Duration) -> Option<Duration> {
+        let previous = match self.previous {
+            Some(previous) if attempt > 1 => previous,
+            _ => retry,
+        };
+        let low = millis(retry);
+        let high = millis(previous).saturating_mul(3).max(low);
+        let delay = Duration::from_millis(fastrand::u64(low..=high)).min(self.max);
+        self.previous = Some(delay);
+        Some(delay)
+    }
+}
+
+/// Rules for giving up on reconnecting, checked before asking the retry policy.
+///
+/// The counters start over whenever a connection succeeds.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryLimits {
+    /// Maximum number of consecutive attempts which fail with an error.
+    pub max_failures: Option<u32>,
+    /// Maximum time without a connection. The client gives up instead of waiting past it.
+    pub max_downtime: Option<Duration>,
+}
+
+/// Why a client stopped reconnecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GiveUpReason {
+    /// The retry policy returned `None`.
+    Policy,
+    /// This many consecutive attempts failed, see `RetryLimits::max_failures`.
+    MaxFailures(u32),
+    /// The connection was down for too long, see `RetryLimits::max_downtime`.
+    MaxDowntime(Duration),
+    /// The error predicate matched the last error.
+    Predicate,
+}
+
+impl fmt::Display for GiveUpReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GiveUpReason::Policy => write!(f, "retry policy gave up"),
+            GiveUpReason::MaxFailures(n) => write!(f, "{} consecutive failures", n),
+            GiveUpReason::MaxDowntime(max) => write!(f, "down for more than {:?}", max),
+            GiveUpReason::Predicate => write!(f, "error is not retried"),
+        }
+    }
+}
+
+/// Reconnection state shared by the clients.
+pub(crate) struct RetryState {
+    pub(crate) policy: Box<dyn RetryPolicy>,
+    pub(crate) give_up_on: Option<ErrorPredicate>,
+    pub(crate) is_fatal: ErrorPredicate,
+    attempt: u32,
+    failures: u32,
+    down_since: Option<Instant>,
+}
+
+impl RetryState {
+    pub(crate) fn new() -> RetryState {
+        RetryState {
+            policy: Box::new(Fixed),
+            give_up_on: None,
+            is_fatal: Box::new(Error::is_fatal),
+            attempt: 0,
+            failures: 0,
+            down_since: None,
+        }
+    }
+
+    pub(crate) fn is_fatal(&self, error: &Error) -> bool {
+        (self.is_fatal)(error)
+    }
+
+    /// Returns the number of the current reconnection attempt, 0 before the first failure.
+    pub(crate) fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Starts counting from scratch after a successful connection.
+    pub(crate) fn connected(&mut self) {
+        self.attempt = 0;
+        self.failures = 0;
+        self.down_since = None;
+    }
+
+    /// Returns the delay before the next attempt after the connection ended with `error`.
+    pub(crate) fn next_delay(
+        &mut self,
+        error: Option<&Error>,
+        retry: Duration,
+        limits: &RetryLimits,
+    ) -> Result<Duration, GiveUpReason> {
+        let down_since = *self.down_since.get_or_insert_with(Instant::now);
+        self.attempt = self.attempt.saturating_add(1);
+        if let Some(error) = error {
+            self.failures = self.failures.saturating_add(1);
+            if self.give_up_on.as_ref().is_some_and(|f| f(error)) {
+                return Err(GiveUpReason::Predicate);
+            }
+        }
+        if let Some(max) = limits.max_failures {
+            if self.failures >= max {
+                return Err(GiveUpReason::MaxFailures(self.failures));
+            }
+        }
+        let delay = self
+            .policy
+            .next_delay(self.attempt, error, retry)
+            .ok_or(GiveUpReason::Policy)?;
+        if let Some(max) = limits.max_downtime {
+            if down_since.elapsed() + delay > max {
+                return Err(GiveUpReason::MaxDowntime(max));
+            }
+        }
+        Ok(delay)
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().min(u128::from(u64::MAX)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delays<P: RetryPolicy>(mut policy: P, retry: Duration, attempts: u32) -> Vec<Duration> {
+        (1..=attempts)
+            .map(|attempt| policy.next_delay(attempt, None, retry).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn fixed() {
+        let retry = Duration::from_millis(42);
+        assert_eq!(delays(Fixed, retry, 3), vec![retry; 3]);
+    }
+
+    #[test]
+    fn exponential() {
+        let policy = Exponential::new(Duration::from_secs(5));
+        let delays = delays(policy, Duration::from_secs(1), 5);
+        let secs: Vec<u64> = delays.iter().map(Duration::as_secs).collect();
+        assert_eq!(secs, vec![1, 2, 4, 5, 5]);
+
+        // Huge exponents must not overflow.
+        let mut policy = Exponential::new(Duration::from_secs(5));
+        let delay = policy.next_delay(1000, None, Duration::from_secs(1));
+        assert_eq!(delay, Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn exponential_factor_zero() {
+        let policy = Exponential {
+            factor: 0,
+            max: Duration::from_secs(5),
+        };
+        assert_eq!(
+            delays(policy, Duration::from_secs(1), 3),
+            vec![Duration::from_secs(1); 3]
+        );
+    }
+
+    #[test]
+    fn decorrelated_jitter() {
+        let retry = Duration::from_millis(100);
+        let max = Duration::from_secs(2);
+        let mut policy = DecorrelatedJitter::new(max);
+        let mut previous = retry;
+        for attempt in 1..100 {
+            let delay = policy.next_delay(attempt, None, retry).unwrap();
+            assert!(delay >= retry);
+            assert!(delay <= max);
+            assert!(delay <= previous * 3);
+            previous = delay;
+        }
+
+        // The first attempt after a successful connection starts over.
+        let delay = policy.next_delay(1, None, retry).unwrap();
+        assert!(delay <= retry * 3);
+    }
+
+    #[test]
+    fn function() {
+        fn give_up(attempt: u32, _: Option<&Error>, retry: Duration) -> Option<Duration> {
+            if attempt < 3 {
+                Some(retry)
+            } else {
+                None
+            }
+        }
+
+        let mut policy = give_up;
+        assert!(policy.next_delay(2, None, Duration::from_secs(1)).is_some());
+        assert!(policy.next_delay(3, None, Duration::from_secs(1)).is_none());
+    }
+
+    #[test]
+    fn max_failures() {
+        let limits = RetryLimits {
+            max_failures: Some(2),
+            ..RetryLimits::default()
+        };
+        let error = Error::from("connection refused");
+        let retry = Duration::from_secs(1);
+        let mut state = RetryState::new();
+
+        // Closed streams don't count as failures.
+        assert_eq!(state.next_delay(None, retry, &limits), Ok(retry));
+        assert_eq!(state.next_delay(Some(&error), retry, &limits), Ok(retry));
+        state.connected();
+        assert_eq!(state.next_delay(Some(&error), retry, &limits), Ok(retry));
+        assert_eq!(
+            state.next_delay(Some(&error), retry, &limits),
+            Err(GiveUpReason::MaxFailures(2))
+        );
+    }
+
+    #[test]
+    fn max_downtime() {
+        let limits = RetryLimits {
+            max_downtime: Some(Duration::from_secs(10)),
+            ..RetryLimits::default()
+        };
+        let mut state = RetryState::new();
+        let delay = state.next_delay(None, Duration::from_secs(5), &limits);
+        assert_eq!(delay, Ok(Duration::from_secs(5)));
+        let delay = state.next_delay(None, Duration::from_secs(15), &limits);
+        assert_eq!(
+            delay,
+            Err(GiveUpReason::MaxDowntime(Duration::from_secs(10)))
+        );
+    }
+}
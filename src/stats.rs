@@ -0,0 +1,290 @@
+//! # Client metrics
+//!
+//! Clients count events, bytes, reconnects and errors while running. `Client::stats()` returns a
+//! `ClientStats` snapshot of these counters. With the `prometheus` feature, a snapshot can be
+//! written in the Prometheus text format, so that services using this crate report the same
+//! metrics.
+
+use super::event::Event;
+use super::reqwest::{Error, ErrorKind};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Counters of a client, see `Client::stats()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    /// Number of events by event type. Events without an `event` field have the type `message`.
+    pub events: BTreeMap<String, u64>,
+    /// Number of bytes read from response bodies.
+    pub bytes_read: u64,
+    /// Number of reconnects the client scheduled.
+    pub reconnects: u64,
+    /// Number of errors the client ran into, by kind.
+    ///
+    /// This includes errors which ended a connection that the client silently reopened. When the
+    /// client gives up, both the last error and the `gave_up` error count.
+    ///
+    /// The kind is the name of the `ErrorKind` variant in snake case, e.g. `http` or
+    /// `idle_timeout`. Errors created from a message count as `other`.
+    pub errors: BTreeMap<String, u64>,
+    /// Total time with an open connection, including the current one.
+    pub time_connected: Duration,
+    /// Delay before the next reconnect, as decided by the retry policy.
+    pub retry_delay: Duration,
+}
+
+#[cfg(feature = "prometheus")]
+impl ClientStats {
+    /// Returns the metrics in the Prometheus text exposition format.
+    ///
+    /// `labels` are added to every metric, e.g. to tell several clients apart.
+    ///
+    /// Requires the `prometheus` feature.
+    pub fn to_prometheus(&self, labels: &[(&str, &str)]) -> String {
+        let mut out = String::new();
+        let name = "events_total";
+        header(&mut out, name, "counter", "Events received, by event type.");
+        for (event_type, n) in &self.events {
+            sample(&mut out, name, labels, Some(("type", event_type)), n);
+        }
+        let name = "bytes_read_total";
+        header(
+            &mut out,
+            name,
+            "counter",
+            "Bytes read from response bodies.",
+        );
+        sample(&mut out, name, labels, None, self.bytes_read);
+        let name = "reconnects_total";
+        header(&mut out, name, "counter", "Reconnects scheduled.");
+        sample(&mut out, name, labels, None, self.reconnects);
+        let name = "errors_total";
+        header(
+            &mut out,
+            name,
+            "counter",
+            "Errors the client ran into, by kind.",
+        );
+        for (kind, n) in &self.errors {
+            sample(&mut out, name, labels, Some(("kind", kind)), n);
+        }
+        let name = "connected_seconds_total";
+        header(&mut out, name, "counter", "Time with an open connection.");
+        let seconds = self.time_connected.as_secs_f64();
+        sample(&mut out, name, labels, None, seconds);
+        let name = "retry_delay_seconds";
+        header(&mut out, name, "gauge", "Delay before the next reconnect.");
+        sample(&mut out, name, labels, None, self.retry_delay.as_secs_f64());
+        out
+    }
+}
+
+#[cfg(feature = "prometheus")]
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    out.push_str(&format!("# HELP eventsource_{} {}\n", name, help));
+    out.push_str(&format!("# TYPE eventsource_{} {}\n", name, kind));
+}
+
+#[cfg(feature = "prometheus")]
+fn sample<T: std::fmt::Display>(
+    out: &mut String,
+    name: &str,
+    labels: &[(&str, &str)],
+    extra: Option<(&str, &str)>,
+    value: T,
+) {
+    let labels: Vec<String> = labels
+        .iter()
+        .cloned()
+        .chain(extra)
+        .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+        .collect();
+    if labels.is_empty() {
+        out.push_str(&format!("eventsource_{} {}\n", name, value));
+    } else {
+        out.push_str(&format!(
+            "eventsource_{}{{{}}} {}\n",
+            name,
+            labels.join(","),
+            value
+        ));
+    }
+}
+
+#[cfg(feature = "prometheus")]
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Live counters of a client.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    events: BTreeMap<String, u64>,
+    bytes_read: Arc<AtomicU64>,
+    reconnects: u64,
+    errors: BTreeMap<String, u64>,
+    time_connected: Duration,
+    connected_since: Option<Instant>,
+}
+
+impl Metrics {
+    /// Counts an event returned by the client.
+    pub(crate) fn event(&mut self, event: &Event) {
+        let event_type = event.event_type.as_deref().unwrap_or("message");
+        *self.events.entry(event_type.to_string()).or_insert(0) += 1;
+    }
+
+    /// Counts an error, whether the client returns it or handles it by reconnecting.
+    pub(crate) fn error(&mut self, error: &Error) {
+        *self
+            .errors
+            .entry(error_kind(error).to_string())
+            .or_insert(0) += 1;
+    }
+
+    #[cfg(feature = "with-reqwest-async")]
+    pub(crate) fn bytes_read(&self, n: usize) {
+        self.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reconnect(&mut self) {
+        self.reconnects += 1;
+    }
+
+    pub(crate) fn connected(&mut self) {
+        self.connected_since = Some(Instant::now());
+    }
+
+    pub(crate) fn disconnected(&mut self) {
+        if let Some(since) = self.connected_since.take() {
+            self.time_connected += since.elapsed();
+        }
+    }
+
+    /// Wraps a response body, counting the bytes read from it.
+    pub(crate) fn count_bytes<R: Read>(&self, inner: R) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            bytes_read: self.bytes_read.clone(),
+        }
+    }
+
+    pub(crate) fn snapshot(&self, retry_delay: Duration) -> ClientStats {
+        let current = self
+            .connected_since
+            .map_or(Duration::from_secs(0), |t| t.elapsed());
+        ClientStats {
+            events: self.events.clone(),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            reconnects: self.reconnects,
+            errors: self.errors.clone(),
+            time_connected: self.time_connected + current,
+            retry_delay,
+        }
+    }
+}
+
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    bytes_read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Returns the name of the error's kind used in `ClientStats::errors`.
+fn error_kind(error: &Error) -> &'static str {
+    match error.kind() {
+        ErrorKind::Reqwest(_) => "reqwest",
+        ErrorKind::Io(_) => "io",
+        ErrorKind::Http(_) => "http",
+        ErrorKind::InvalidContentType(_) => "invalid_content_type",
+        ErrorKind::MalformedContentType(_) => "malformed_content_type",
+        ErrorKind::NoContentType => "no_content_type",
+        ErrorKind::LimitExceeded(_) => "limit_exceeded",
+        ErrorKind::InvalidUtf8(_) => "invalid_utf8",
+        ErrorKind::IdleTimeout(_) => "idle_timeout",
+        ErrorKind::GaveUp(_) => "gave_up",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let mut metrics = Metrics::default();
+        let event = Event {
+            event_type: Some("price".into()),
+            ..Event::default()
+        };
+        metrics.event(&event);
+        metrics.event(&Event::default());
+        metrics.event(&Event::default());
+        metrics.error(&ErrorKind::NoContentType.into());
+        metrics.error(&"custom".into());
+
+        let stats = metrics.snapshot(Duration::from_secs(1));
+        let events: Vec<_> = stats.events.into_iter().collect();
+        assert_eq!(events, vec![("message".into(), 2), ("price".into(), 1)]);
+        let errors: Vec<_> = stats.errors.into_iter().collect();
+        assert_eq!(
+            errors,
+            vec![("no_content_type".into(), 1), ("other".into(), 1)]
+        );
+        assert_eq!(stats.time_connected, Duration::from_secs(0));
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn prometheus() {
+        let mut stats = ClientStats {
+            bytes_read: 42,
+            reconnects: 3,
+            time_connected: Duration::from_millis(1500),
+            retry_delay: Duration::from_secs(5),
+            ..ClientStats::default()
+        };
+        stats.events.insert("price".into(), 7);
+        stats.errors.insert("http".into(), 2);
+
+        let text = stats.to_prometheus(&[("feed", "a\"b")]);
+        assert_eq!(
+            text,
+            "# HELP eventsource_events_total Events received, by event type.\n\
+             # TYPE eventsource_events_total counter\n\
+             eventsource_events_total{feed=\"a\\\"b\",type=\"price\"} 7\n\
+             # HELP eventsource_bytes_read_total Bytes read from response bodies.\n\
+             # TYPE eventsource_bytes_read_total counter\n\
+             eventsource_bytes_read_total{feed=\"a\\\"b\"} 42\n\
+             # HELP eventsource_reconnects_total Reconnects scheduled.\n\
+             # TYPE eventsource_reconnects_total counter\n\
+             eventsource_reconnects_total{feed=\"a\\\"b\"} 3\n\
+             # HELP eventsource_errors_total Errors the client ran into, by kind.\n\
+             # TYPE eventsource_errors_total counter\n\
+             eventsource_errors_total{feed=\"a\\\"b\",kind=\"http\"} 2\n\
+             # HELP eventsource_connected_seconds_total Time with an open connection.\n\
+             # TYPE eventsource_connected_seconds_total counter\n\
+             eventsource_connected_seconds_total{feed=\"a\\\"b\"} 1.5\n\
+             # HELP eventsource_retry_delay_seconds Delay before the next reconnect.\n\
+             # TYPE eventsource_retry_delay_seconds gauge\n\
+             eventsource_retry_delay_seconds{feed=\"a\\\"b\"} 5\n"
+        );
+        assert!(ClientStats::default()
+            .to_prometheus(&[])
+            .contains("\neventsource_reconnects_total 0\n"));
+    }
+}
@@ -0,0 +1,151 @@
+//! # Persistent last event IDs
+//!
+//! A client sends the ID of the last event it received when reconnecting, so that the server can
+//! continue the stream. A `LastEventIdStore` keeps this ID beyond the lifetime of the client, e.g.
+//! to resume after a restart of the process.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use eventsource::reqwest::Client;
+//! use eventsource::store::FileStore;
+//! use reqwest::Url;
+//!
+//! let mut client = Client::new(Url::parse("http://example.com").unwrap());
+//! client.last_event_id_store(FileStore::new("last-event-id")).unwrap();
+//! for event in client {
+//!     println!("{}", event.unwrap());
+//! }
+//! ```
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Loads and saves the last event ID of a client.
+pub trait LastEventIdStore: Send {
+    /// Returns the saved ID, or `None` if there is none.
+    fn load(&mut self) -> io::Result<Option<String>>;
+
+    /// Saves the ID of the last event. `None` means that the server reset the ID.
+    fn save(&mut self, id: Option<&str>) -> io::Result<()>;
+}
+
+/// Keeps the ID in memory.
+///
+/// Clones share the same ID, so a clone can read the ID while the client uses the store.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    id: Arc<Mutex<Option<String>>>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// Creates a store containing `id`.
+    pub fn with_id<S: Into<String>>(id: S) -> MemoryStore {
+        MemoryStore {
+            id: Arc::new(Mutex::new(Some(id.into()))),
+        }
+    }
+
+    /// Returns the saved ID.
+    pub fn get(&self) -> Option<String> {
+        self.id.lock().unwrap().clone()
+    }
+}
+
+impl LastEventIdStore for MemoryStore {
+    fn load(&mut self) -> io::Result<Option<String>> {
+        Ok(self.get())
+    }
+
+    fn save(&mut self, id: Option<&str>) -> io::Result<()> {
+        *self.id.lock().unwrap() = id.map(str::to_string);
+        Ok(())
+    }
+}
+
+/// Keeps the ID in a file.
+///
+/// The file is replaced atomically, so a crash while saving leaves either the old or the new ID
+/// behind. A missing or empty file means that there is no ID.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    /// Creates a store using the file at `path`.
+    ///
+    /// Saving writes a temporary file next to it first, so the directory has to be writable.
+    pub fn new<P: AsRef<Path>>(path: P) -> FileStore {
+        FileStore {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl LastEventIdStore for FileStore {
+    fn load(&mut self) -> io::Result<Option<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(ref id) if id.is_empty() => Ok(None),
+            Ok(id) => Ok(Some(id)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn save(&mut self, id: Option<&str>) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(id.unwrap_or("").as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_store() {
+        let mut store = MemoryStore::new();
+        assert_eq!(store.load().unwrap(), None);
+        let other = store.clone();
+        store.save(Some("42")).unwrap();
+        assert_eq!(other.get(), Some("42".to_string()));
+        store.save(None).unwrap();
+        assert_eq!(store.load().unwrap(), None);
+    }
+
+    #[test]
+    fn file_store() {
+        let path = std::env::temp_dir().join(format!("eventsource-{}", std::process::id()));
+        let mut store = FileStore::new(&path);
+        assert_eq!(store.load().unwrap(), None);
+        store.save(Some("42")).unwrap();
+        assert_eq!(
+            FileStore::new(&path).load().unwrap(),
+            Some("42".to_string())
+        );
+        store.save(Some("caf\u{e9}\n2")).unwrap();
+        assert_eq!(store.load().unwrap(), Some("caf\u{e9}\n2".to_string()));
+        store.save(None).unwrap();
+        assert_eq!(store.load().unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
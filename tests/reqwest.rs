@@ -1,20 +1,41 @@
-use eventsource::reqwest::{Client, Error, ErrorKind};
-use reqwest::Url;
-use std::time::Duration;
+#![cfg(feature = "with-reqwest")]
+#![allow(clippy::assertions_on_constants)]
+
+use eventsource::dispatch::EventSource;
+use eventsource::event::{DispatchMode, LimitExceeded, LimitPolicy};
+use eventsource::reqwest::{Client, ConnectionInfo, Error, ErrorKind, ReadyState};
+use eventsource::retry::GiveUpReason;
+use eventsource::store::MemoryStore;
+use reqwest::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Method, StatusCode, Url};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use server::Server;
 mod server;
 
 fn server() -> Server {
     let s = Server::new();
-    s.receive(
+    expect_request(&s, "/");
+    s
+}
+
+fn expect_request(s: &Server, path: &str) {
+    expect_request_with_headers(s, path, "");
+}
+
+fn expect_request_with_headers(s: &Server, path: &str, headers: &str) {
+    s.receive(&format!(
         "\
-         GET / HTTP/1.1\r\n\
+         GET {} HTTP/1.1\r\n\
          host: 127.0.0.1:$PORT\r\n\
          accept: text/event-stream\r\n\
+         cache-control: no-cache\r\n\
+         {}\
          \r\n",
-    );
-    return s;
+        path, headers
+    ));
 }
 
 #[test]
@@ -64,6 +85,7 @@ fn retry() {
     let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
     let event = client.next().unwrap().unwrap();
     assert_eq!(event.data, "bar\n");
+    assert_eq!(event.retry, Some(Duration::from_millis(42)));
     assert_eq!(client.retry, Duration::from_millis(42));
 }
 
@@ -102,6 +124,27 @@ fn invalid_content_type() {
     }
 }
 
+#[test]
+fn malformed_content_type() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: event-stream\r\n\
+         \r\n",
+    );
+    expect_request(&s, "/");
+    s.send_bytes(b"HTTP/1.1 200 OK\r\nContent-Type: text/\xffvent-stream\r\n\r\n");
+
+    for expected in &["event-stream", "text/\u{fffd}vent-stream"] {
+        let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+        match client.next().unwrap() {
+            Err(Error(ErrorKind::MalformedContentType(value), _)) => assert_eq!(&value, expected),
+            _ => panic!("MalformedContentType error expected"),
+        }
+        assert!(client.next().is_none());
+    }
+}
+
 #[test]
 fn content_type_with_mime_parameter() {
     let s = server();
@@ -120,3 +163,912 @@ fn content_type_with_mime_parameter() {
         .expect("MIME parameter should be ignored");
     assert_eq!(event.data, "bar\n");
 }
+
+#[test]
+fn bom_and_invalid_utf8() {
+    let s = server();
+    s.send_bytes(
+        b"HTTP/1.1 200 OK\r\n\
+          Content-Type: text/event-stream\r\n\
+          \r\n\
+          \xEF\xBB\xBFdata: a\xFFb\r\n\
+          \r\n\
+          data: c\r\r",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "a\u{FFFD}b\n");
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "c\n");
+}
+
+#[test]
+fn invalid_utf8_warning() {
+    let s = server();
+    s.send_bytes(
+        b"HTTP/1.1 200 OK\r\n\
+          Content-Type: text/event-stream\r\n\
+          \r\n\
+          data: a\xFF\n\
+          \n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.parser_options.warn_invalid_utf8 = true;
+    match client.next().unwrap() {
+        Err(Error(ErrorKind::InvalidUtf8(line), _)) => assert_eq!(line, "data: a\u{FFFD}"),
+        _ => panic!("InvalidUtf8 error expected"),
+    }
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "a\u{FFFD}\n");
+}
+
+#[test]
+fn comments() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         : keepalive\r\n\
+         \r\n\
+         :\r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let comments = Arc::new(Mutex::new(Vec::new()));
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    let c = comments.clone();
+    client.on_comment(move |text| c.lock().unwrap().push(text.to_string()));
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+    assert_eq!(*comments.lock().unwrap(), vec!["keepalive", ""]);
+}
+
+#[test]
+fn line_length_limit() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: 0123456789\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.parser_options.limits.max_line_length = Some(10);
+    client.parser_options.limits.policy = LimitPolicy::DropEvent;
+    match client.next().unwrap() {
+        Err(Error(ErrorKind::LimitExceeded(LimitExceeded::LineLength), _)) => (),
+        _ => panic!("LimitExceeded error expected"),
+    }
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+}
+
+#[test]
+fn retry_policy_gives_up() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 500 Internal Server Error\r\n\
+         \r\n",
+    );
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    let c = calls.clone();
+    client.fatal_errors(|_| false);
+    client.retry_policy(move |attempt, error: Option<&Error>, retry| {
+        let status = match error {
+            Some(Error(ErrorKind::Http(status), _)) => Some(status.as_u16()),
+            _ => None,
+        };
+        c.lock().unwrap().push((attempt, status, retry));
+        None
+    });
+
+    match client.next().unwrap() {
+        Err(Error(ErrorKind::GaveUp(GiveUpReason::Policy), _)) => (),
+        _ => panic!("GaveUp error expected"),
+    }
+    assert!(client.next().is_none());
+    assert!(client.next().is_none());
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![(1, Some(500), Duration::from_millis(5000))]
+    );
+}
+
+#[test]
+fn max_failures() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 500 Internal Server Error\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.fatal_errors(|_| false);
+    client.retry_limits.max_failures = Some(1);
+    let err = client.next().unwrap().unwrap_err();
+    match err {
+        Error(ErrorKind::GaveUp(GiveUpReason::MaxFailures(1)), _) => (),
+        _ => panic!("GaveUp error expected"),
+    }
+    let cause = err.iter().nth(1).expect("last error should be the cause");
+    assert_eq!(
+        cause.to_string(),
+        "HTTP status code: 500 Internal Server Error"
+    );
+    assert!(client.next().is_none());
+}
+
+#[test]
+fn give_up_on_error() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/plain\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.fatal_errors(|_| false);
+    client.give_up_on(|err| matches!(err.kind(), ErrorKind::InvalidContentType(_)));
+    match client.next().unwrap() {
+        Err(Error(ErrorKind::GaveUp(GiveUpReason::Predicate), _)) => (),
+        _ => panic!("GaveUp error expected"),
+    }
+    assert!(client.next().is_none());
+}
+
+#[test]
+fn http_error_is_fatal() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 500 Internal Server Error\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    let err = client.next().unwrap().unwrap_err();
+    assert!(err.is_fatal());
+    match err {
+        Error(ErrorKind::Http(status), _) => assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR),
+        _ => panic!("Http error expected"),
+    }
+    assert!(client.next().is_none());
+}
+
+#[test]
+fn retry_bad_gateway() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 502 Bad Gateway\r\n\
+         \r\n",
+    );
+    expect_request(&s, "/");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.fatal_errors(|err| match err.kind() {
+        ErrorKind::Http(StatusCode::BAD_GATEWAY) => false,
+        _ => err.is_fatal(),
+    });
+    let err = client.next().unwrap().unwrap_err();
+    assert!(err.is_fatal());
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+}
+
+#[test]
+fn no_content_stops() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 204 No Content\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    assert!(client.next().is_none());
+    assert!(client.next().is_none());
+}
+
+#[test]
+fn lifecycle_callbacks() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 500 Internal Server Error\r\n\
+         \r\n",
+    );
+    expect_request(&s, "/");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+    expect_request(&s, "/");
+    s.send(
+        "HTTP/1.1 204 No Content\r\n\
+         \r\n",
+    );
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.fatal_errors(|_| false);
+    let l = log.clone();
+    client.on_open(move |attempt, status| {
+        l.lock()
+            .unwrap()
+            .push(format!("open {} {}", attempt, status.as_u16()))
+    });
+    let l = log.clone();
+    client.on_error(move |err| l.lock().unwrap().push(format!("error {}", err)));
+    let l = log.clone();
+    client.on_reconnect(move |attempt, delay| {
+        l.lock()
+            .unwrap()
+            .push(format!("reconnect {} {:?}", attempt, delay))
+    });
+
+    assert_eq!(client.ready_state(), ReadyState::Connecting);
+    assert!(client.next().unwrap().is_err());
+    assert_eq!(client.ready_state(), ReadyState::Connecting);
+    assert_eq!(client.next().unwrap().unwrap().data, "foo\n");
+    assert_eq!(client.ready_state(), ReadyState::Open);
+    assert!(client.next().is_none());
+    assert_eq!(client.ready_state(), ReadyState::Closed);
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "error HTTP status code: 500 Internal Server Error",
+            "reconnect 1 10ms",
+            "open 1 200",
+            "reconnect 1 10ms",
+        ]
+    );
+}
+
+#[test]
+fn reconnect_after_redirect() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 301 Moved Permanently\r\n\
+         Location: /events\r\n\
+         \r\n",
+    );
+    expect_request_with_headers(&s, "/events", "referer: http://127.0.0.1:$PORT/\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+    expect_request(&s, "/events");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "foo\n");
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+}
+
+#[test]
+fn unsendable_event_id() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         id: caf\u{e9}\r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+    expect_request_with_headers(&s, "/", "last-event-id: caf\u{e9}\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         id: bell\u{7}\r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+    // The ID can't be sent in a header, so the request goes without one.
+    expect_request(&s, "/");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: baz\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    let events: Vec<_> = client.by_ref().take(3).map(Result::unwrap).collect();
+    assert_eq!(events[0].id.as_deref(), Some("caf\u{e9}"));
+    assert_eq!(events[1].id.as_deref(), Some("bell\u{7}"));
+    assert_eq!(events[2].data, "baz\n");
+}
+
+#[test]
+fn resume_from_store() {
+    let s = Server::new();
+    expect_request_with_headers(&s, "/", "last-event-id: 41\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         id: 42\r\n\
+         data: foo\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let store = MemoryStore::with_id("41");
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.set_last_event_id(Some("40".to_string()));
+    client.last_event_id_store(store.clone()).unwrap();
+    assert_eq!(client.last_event_id(), Some("41"));
+    client.next().unwrap().unwrap();
+    assert_eq!(store.get(), Some("42".to_string()));
+    client.next().unwrap().unwrap();
+    assert_eq!(client.last_event_id(), Some("42"));
+}
+
+#[test]
+fn last_event_id_without_data() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         id: 7\r\n\
+         \r\n",
+    );
+    expect_request_with_headers(&s, "/", "last-event-id: 7\r\n");
+    s.send(
+        "HTTP/1.1 204 No Content\r\n\
+         \r\n",
+    );
+
+    let store = MemoryStore::new();
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.parser_options.dispatch_mode = DispatchMode::Whatwg;
+    client.last_event_id_store(store.clone()).unwrap();
+    assert!(client.next().is_none());
+    assert_eq!(client.last_event_id(), Some("7"));
+    assert_eq!(store.get(), Some("7".to_string()));
+}
+
+#[test]
+fn last_event_id_of_incomplete_block() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         id: 1\n\
+         data: a\n\
+         \n\
+         id: 2\n\
+         data: b\n",
+    );
+    // Event 2 was cut off, so the server has to send it again.
+    expect_request_with_headers(&s, "/", "last-event-id: 1\r\n");
+    s.send(
+        "HTTP/1.1 204 No Content\r\n\
+         \r\n",
+    );
+
+    let store = MemoryStore::new();
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.parser_options.dispatch_mode = DispatchMode::Whatwg;
+    client.last_event_id_store(store.clone()).unwrap();
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "a");
+    assert!(client.next().is_none());
+    assert_eq!(client.last_event_id(), Some("1"));
+    assert_eq!(store.get(), Some("1".to_string()));
+}
+
+#[test]
+fn seed_last_event_id() {
+    let s = Server::new();
+    expect_request_with_headers(&s, "/", "last-event-id: 40\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.set_last_event_id(Some("40".to_string()));
+    // An empty store keeps the seeded ID.
+    client.last_event_id_store(MemoryStore::new()).unwrap();
+    client.next().unwrap().unwrap();
+}
+
+#[test]
+fn connection_info() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         X-Request-Id: first\r\n\
+         \r\n\
+         id: 1\r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+    expect_request_with_headers(&s, "/", "last-event-id: 1\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         X-Request-Id: second\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let url = Url::parse(&s.url("/")).unwrap();
+    let mut client = Client::new(url.clone());
+    client.retry = Duration::from_millis(10);
+    assert!(client.connection_info().is_none());
+    let before = SystemTime::now();
+    client.next().unwrap().unwrap();
+    let info: ConnectionInfo = client.connection_info().unwrap().clone();
+    assert_eq!(info.status, StatusCode::OK);
+    assert_eq!(info.headers["x-request-id"], "first");
+    assert_eq!(info.url, url);
+    assert_eq!(info.remote_addr, Some(*s.addr()));
+    assert_eq!(info.attempt, 0);
+    assert!(info.connected_at >= before);
+    assert_eq!(info.last_event_id, None);
+
+    client.next().unwrap().unwrap();
+    let info = client.connection_info().unwrap();
+    assert_eq!(info.headers["x-request-id"], "second");
+    assert_eq!(info.attempt, 1);
+    assert_eq!(info.last_event_id.as_deref(), Some("1"));
+}
+
+#[test]
+fn client_stats() {
+    let body = "event: price\r\n\
+                data: 42\r\n\
+                \r\n\
+                data: foo\r\n\
+                \r\n";
+    let s = server();
+    s.send(
+        "HTTP/1.1 500 Internal Server Error\r\n\
+         \r\n",
+    );
+    expect_request(&s, "/");
+    s.send(&format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         {}",
+        body
+    ));
+    expect_request(&s, "/");
+    s.send(
+        "HTTP/1.1 204 No Content\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.fatal_errors(|_| false);
+    assert_eq!(client.by_ref().count(), 3);
+
+    let stats = client.stats();
+    assert_eq!(stats.events["price"], 1);
+    assert_eq!(stats.events["message"], 1);
+    assert_eq!(stats.errors.len(), 1);
+    assert_eq!(stats.errors["http"], 1);
+    assert_eq!(stats.bytes_read, body.len() as u64);
+    assert_eq!(stats.reconnects, 2);
+    assert!(stats.time_connected > Duration::from_secs(0));
+    assert_eq!(stats.retry_delay, Duration::from_millis(10));
+}
+
+#[test]
+fn client_stats_count_dropped_streams() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         Content-Length: 100\r\n\
+         \r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+    expect_request(&s, "/");
+    s.send(
+        "HTTP/1.1 204 No Content\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "foo\n");
+    // The stream breaks off and the client reconnects without returning an error.
+    assert!(client.next().is_none());
+
+    let stats = client.stats();
+    assert_eq!(stats.errors.len(), 1);
+    assert_eq!(stats.errors["io"], 1);
+    assert_eq!(stats.reconnects, 1);
+}
+
+#[test]
+fn refresh_credentials() {
+    let s = Server::new();
+    expect_request_with_headers(&s, "/", "authorization: Bearer 1\r\n");
+    s.send(
+        "HTTP/1.1 401 Unauthorized\r\n\
+         \r\n",
+    );
+    expect_request_with_headers(&s, "/", "authorization: Bearer 2\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let token = Arc::new(Mutex::new(1));
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    let t = token.clone();
+    client.on_request(move |headers| {
+        let value = format!("Bearer {}", t.lock().unwrap());
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&value).unwrap());
+    });
+    let t = token.clone();
+    client.on_auth_error(move |status| {
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        *t.lock().unwrap() += 1;
+        true
+    });
+
+    match client.next().unwrap() {
+        Err(Error(ErrorKind::Http(StatusCode::UNAUTHORIZED), _)) => (),
+        _ => panic!("Http error expected"),
+    }
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+}
+
+#[test]
+fn auth_error_with_fatal_errors() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 401 Unauthorized\r\n\
+         \r\n",
+    );
+    expect_request(&s, "/");
+    s.send(
+        "HTTP/1.1 403 Forbidden\r\n\
+         \r\n",
+    );
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.fatal_errors(|_| false);
+    let c = calls.clone();
+    client.on_auth_error(move |status| {
+        let mut calls = c.lock().unwrap();
+        calls.push(status);
+        calls.len() == 1
+    });
+
+    match client.next().unwrap() {
+        Err(Error(ErrorKind::Http(StatusCode::UNAUTHORIZED), _)) => (),
+        _ => panic!("Http error expected"),
+    }
+    match client.next().unwrap() {
+        Err(Error(ErrorKind::Http(StatusCode::FORBIDDEN), _)) => (),
+        _ => panic!("Http error expected"),
+    }
+    assert!(client.next().is_none());
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN]
+    );
+}
+
+#[test]
+fn post_with_body() {
+    let s = Server::new();
+    for data in &["foo", "bar"] {
+        s.receive(
+            "\
+             POST / HTTP/1.1\r\n\
+             host: 127.0.0.1:$PORT\r\n\
+             accept: text/event-stream\r\n\
+             cache-control: no-cache\r\n\
+             content-type: application/json\r\n\
+             content-length: 15\r\n\
+             \r\n\
+             {\"q\": \"search\"}",
+        );
+        s.send(&format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/event-stream\r\n\
+             \r\n\
+             data: {}\r\n\
+             \r\n",
+            data
+        ));
+    }
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.method = Method::POST;
+    client.body = Some(br#"{"q": "search"}"#.to_vec());
+    client
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "foo\n");
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+}
+
+#[test]
+fn no_reconnect() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.reconnect = false;
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+    assert!(client.next().is_none());
+}
+
+#[test]
+fn idle_timeout() {
+    let s = server();
+    s.send_partial(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         id: 1\r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+    s.sleep(Duration::from_millis(500));
+    s.send("");
+    expect_request_with_headers(&s, "/", "last-event-id: 1\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.idle_timeout = Some(Duration::from_millis(100));
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "foo\n");
+    match client.next().unwrap() {
+        Err(Error(ErrorKind::IdleTimeout(timeout), _)) => {
+            assert_eq!(timeout, Duration::from_millis(100))
+        }
+        _ => panic!("IdleTimeout error expected"),
+    }
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+}
+
+#[test]
+fn close_while_waiting() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         retry: 60000\r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    let handle = client.close_handle();
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "foo\n");
+
+    let start = Instant::now();
+    let closer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        handle.close();
+    });
+    assert!(client.next().is_none());
+    assert!(start.elapsed() < Duration::from_secs(5));
+    closer.join().unwrap();
+}
+
+#[test]
+fn close_while_reading() {
+    let s = server();
+    s.send_partial(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+    s.sleep(Duration::from_millis(500));
+    s.send("");
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    let handle = client.close_handle();
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "foo\n");
+
+    let start = Instant::now();
+    let h = handle.clone();
+    let closer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        h.close();
+    });
+    assert!(client.next().is_none());
+    assert!(start.elapsed() < Duration::from_millis(400));
+    assert!(handle.is_closed());
+    assert!(client.next().is_none());
+    closer.join().unwrap();
+}
+
+#[test]
+fn close_handle_after_connecting() {
+    let s = server();
+    s.send_partial(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: foo\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+    s.sleep(Duration::from_millis(3000));
+    s.send("");
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "foo\n");
+    // The connection is already open, so the handle has to take over the current read.
+    let handle = client.close_handle();
+    let event = client.next().unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+
+    let start = Instant::now();
+    let h = handle.clone();
+    let closer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        h.close();
+    });
+    assert!(client.next().is_none());
+    assert!(start.elapsed() < Duration::from_millis(1000));
+    closer.join().unwrap();
+}
+
+#[test]
+fn dispatch_by_event_type() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         event: price\r\n\
+         data: 42\r\n\
+         \r\n\
+         data: hello\r\n\
+         \r\n\
+         event: news\r\n\
+         data: nothing\r\n\
+         \r\n\
+         event: message\r\n\
+         data: world\r\n\
+         \r\n",
+    );
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.reconnect = false;
+    let mut source = EventSource::new(client);
+    let l = log.clone();
+    source.on("price", move |event| {
+        l.lock()
+            .unwrap()
+            .push(format!("price {}", event.data.trim_end()))
+    });
+    let l = log.clone();
+    source.on_message(move |event| {
+        l.lock()
+            .unwrap()
+            .push(format!("message {}", event.data.trim_end()))
+    });
+    let l = log.clone();
+    source.fallback(move |event| {
+        l.lock()
+            .unwrap()
+            .push(format!("other {}", event.data.trim_end()))
+    });
+    source.spawn().join().unwrap();
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "price 42",
+            "message hello",
+            "other nothing",
+            "message world"
+        ]
+    );
+}
+
+#[test]
+fn close_dispatcher() {
+    let s = server();
+    s.send_partial(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n",
+    );
+    s.sleep(Duration::from_secs(1));
+    s.send("");
+
+    let client = Client::new(Url::parse(&s.url("/")).unwrap());
+    let handle = EventSource::new(client).spawn();
+    thread::sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    handle.close();
+    handle.join().unwrap();
+    assert!(start.elapsed() < Duration::from_millis(500));
+}
@@ -0,0 +1,428 @@
+#![cfg(feature = "with-reqwest-async")]
+
+use eventsource::event::DispatchMode;
+use eventsource::reqwest_async::{Client, Error, ErrorKind, ReadyState};
+use eventsource::store::MemoryStore;
+use futures_util::stream::StreamExt;
+use reqwest::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Method, StatusCode, Url};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use server::Server;
+mod server;
+
+fn server() -> Server {
+    let s = Server::new();
+    expect_request(&s, "/");
+    s
+}
+
+fn expect_request(s: &Server, path: &str) {
+    expect_request_with_headers(s, path, "");
+}
+
+fn expect_request_with_headers(s: &Server, path: &str, headers: &str) {
+    s.receive(&format!(
+        "\
+         GET {} HTTP/1.1\r\n\
+         host: 127.0.0.1:$PORT\r\n\
+         accept: text/event-stream\r\n\
+         cache-control: no-cache\r\n\
+         {}\
+         \r\n",
+        path, headers
+    ));
+}
+
+#[tokio::test]
+async fn simple_events() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         id: 42\r\n\
+         event: foo\r\n\
+         data: bar\r\n\
+         \r\n\
+         retry: 42\n\
+         event: bar\n\
+         : comment\n\
+         data: baz\n\
+         \n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    assert_eq!(client.ready_state(), ReadyState::Connecting);
+
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(client.ready_state(), ReadyState::Open);
+    assert_eq!(event.id, Some("42".into()));
+    assert_eq!(event.event_type, Some("foo".into()));
+    assert_eq!(event.data, "bar\n");
+
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(event.id, None);
+    assert_eq!(event.event_type, Some("bar".into()));
+    assert_eq!(event.data, "baz\n");
+    assert_eq!(client.retry, Duration::from_millis(42));
+}
+
+#[tokio::test]
+async fn invalid_content_type() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/plain\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    match client.next().await.unwrap() {
+        Err(Error(ErrorKind::InvalidContentType(_), _)) => (),
+        _ => panic!("InvalidContentType error expected"),
+    }
+    assert_eq!(client.ready_state(), ReadyState::Closed);
+}
+
+#[tokio::test]
+async fn idle_timeout() {
+    let s = server();
+    s.send_partial(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         : keepalive\r\n",
+    );
+    s.sleep(Duration::from_millis(500));
+    s.send("");
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.reconnect = false;
+    client.idle_timeout = Some(Duration::from_millis(100));
+    match client.next().await.unwrap() {
+        Err(Error(ErrorKind::IdleTimeout(_), _)) => (),
+        _ => panic!("IdleTimeout error expected"),
+    }
+    assert!(client.next().await.is_none());
+}
+
+#[tokio::test]
+async fn reconnect_with_last_event_id() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         retry: 200\r\n\
+         id: 1\r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+    expect_request_with_headers(&s, "/", "last-event-id: 1\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(event.data, "foo\n");
+    let start = Instant::now();
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(client.retry, Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn http_error_is_fatal() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 500 Internal Server Error\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    match client.next().await.unwrap() {
+        Err(Error(ErrorKind::Http(StatusCode::INTERNAL_SERVER_ERROR), _)) => (),
+        _ => panic!("Http error expected"),
+    }
+    assert_eq!(client.ready_state(), ReadyState::Closed);
+    assert!(client.next().await.is_none());
+}
+
+#[tokio::test]
+async fn no_content_stops() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 204 No Content\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    assert!(client.next().await.is_none());
+    assert_eq!(client.ready_state(), ReadyState::Closed);
+    assert!(client.next().await.is_none());
+}
+
+#[tokio::test]
+async fn reconnect_after_redirect() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 301 Moved Permanently\r\n\
+         Location: /events\r\n\
+         \r\n",
+    );
+    expect_request_with_headers(&s, "/events", "referer: http://127.0.0.1:$PORT/\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+    expect_request(&s, "/events");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(event.data, "foo\n");
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+}
+
+#[tokio::test]
+async fn refresh_credentials() {
+    let s = Server::new();
+    expect_request_with_headers(&s, "/", "authorization: Bearer 1\r\n");
+    s.send(
+        "HTTP/1.1 401 Unauthorized\r\n\
+         \r\n",
+    );
+    expect_request_with_headers(&s, "/", "authorization: Bearer 2\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let token = Arc::new(Mutex::new(1));
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    let t = token.clone();
+    client.on_request(move |headers| {
+        let value = format!("Bearer {}", t.lock().unwrap());
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&value).unwrap());
+    });
+    let t = token.clone();
+    client.on_auth_error(move |status| {
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        *t.lock().unwrap() += 1;
+        true
+    });
+
+    match client.next().await.unwrap() {
+        Err(Error(ErrorKind::Http(StatusCode::UNAUTHORIZED), _)) => (),
+        _ => panic!("Http error expected"),
+    }
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+}
+
+#[tokio::test]
+async fn post_with_body() {
+    let s = Server::new();
+    for data in &["foo", "bar"] {
+        s.receive(
+            "\
+             POST / HTTP/1.1\r\n\
+             host: 127.0.0.1:$PORT\r\n\
+             accept: text/event-stream\r\n\
+             cache-control: no-cache\r\n\
+             content-type: application/json\r\n\
+             content-length: 15\r\n\
+             \r\n\
+             {\"q\": \"search\"}",
+        );
+        s.send(&format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/event-stream\r\n\
+             \r\n\
+             data: {}\r\n\
+             \r\n",
+            data
+        ));
+    }
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.method = Method::POST;
+    client.body = Some(br#"{"q": "search"}"#.to_vec());
+    client
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(event.data, "foo\n");
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(event.data, "bar\n");
+}
+
+#[tokio::test]
+async fn resume_from_store() {
+    let s = Server::new();
+    expect_request_with_headers(&s, "/", "last-event-id: 41\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         id: 42\r\n\
+         data: foo\r\n\
+         \r\n\
+         id: 43\r\n\
+         \r\n",
+    );
+    expect_request_with_headers(&s, "/", "last-event-id: 43\r\n");
+    s.send(
+        "HTTP/1.1 204 No Content\r\n\
+         \r\n",
+    );
+
+    let store = MemoryStore::with_id("41");
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.parser_options.dispatch_mode = DispatchMode::Whatwg;
+    client.last_event_id_store(store.clone()).unwrap();
+    assert_eq!(client.last_event_id(), Some("41"));
+    client.next().await.unwrap().unwrap();
+    assert_eq!(store.get(), Some("42".to_string()));
+    // The block without data only changes the ID.
+    assert!(client.next().await.is_none());
+    assert_eq!(client.last_event_id(), Some("43"));
+    assert_eq!(store.get(), Some("43".to_string()));
+}
+
+#[tokio::test]
+async fn last_event_id_of_incomplete_block() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         \r\n\
+         id: 1\n\
+         data: a\n\
+         \n\
+         id: 2\n\
+         data: b\n",
+    );
+    // Event 2 was cut off, so the server has to send it again.
+    expect_request_with_headers(&s, "/", "last-event-id: 1\r\n");
+    s.send(
+        "HTTP/1.1 204 No Content\r\n\
+         \r\n",
+    );
+
+    let store = MemoryStore::new();
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    client.parser_options.dispatch_mode = DispatchMode::Whatwg;
+    client.last_event_id_store(store.clone()).unwrap();
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(event.data, "a");
+    assert!(client.next().await.is_none());
+    assert_eq!(client.last_event_id(), Some("1"));
+    assert_eq!(store.get(), Some("1".to_string()));
+}
+
+#[tokio::test]
+async fn connection_info() {
+    let s = server();
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         X-Request-Id: first\r\n\
+         \r\n\
+         id: 1\r\n\
+         data: foo\r\n\
+         \r\n",
+    );
+    expect_request_with_headers(&s, "/", "last-event-id: 1\r\n");
+    s.send(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         X-Request-Id: second\r\n\
+         \r\n\
+         data: bar\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    assert!(client.connection_info().is_none());
+
+    client.next().await.unwrap().unwrap();
+    let info = client.connection_info().unwrap();
+    assert_eq!(info.status, StatusCode::OK);
+    assert_eq!(info.headers["x-request-id"], "first");
+    assert_eq!(info.url.as_str(), s.url("/"));
+    assert_eq!(info.remote_addr, Some(*s.addr()));
+    assert_eq!(info.attempt, 0);
+    assert_eq!(info.last_event_id, None);
+
+    client.next().await.unwrap().unwrap();
+    let info = client.connection_info().unwrap();
+    assert_eq!(info.headers["x-request-id"], "second");
+    assert_eq!(info.attempt, 1);
+    assert_eq!(info.last_event_id.as_deref(), Some("1"));
+}
+
+#[tokio::test]
+async fn client_stats() {
+    let body = "event: price\r\n\
+                data: 42\r\n\
+                \r\n";
+    let s = server();
+    s.send(&format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         Content-Length: 100\r\n\
+         \r\n\
+         {}",
+        body
+    ));
+    expect_request(&s, "/");
+    s.send(
+        "HTTP/1.1 204 No Content\r\n\
+         \r\n",
+    );
+
+    let mut client = Client::new(Url::parse(&s.url("/")).unwrap());
+    client.retry = Duration::from_millis(10);
+    let event = client.next().await.unwrap().unwrap();
+    assert_eq!(event.data, "42\n");
+    // The stream breaks off and the client reconnects without returning an error.
+    assert!(client.next().await.is_none());
+
+    let stats = client.stats();
+    assert_eq!(stats.events["price"], 1);
+    assert_eq!(stats.errors.len(), 1);
+    assert_eq!(stats.errors["reqwest"], 1);
+    assert_eq!(stats.bytes_read, body.len() as u64);
+    assert_eq!(stats.reconnects, 1);
+    assert!(stats.time_connected > Duration::from_secs(0));
+}
@@ -1,4 +1,11 @@
 #![allow(dead_code)]
+#![allow(
+    clippy::iter_skip_next,
+    clippy::len_zero,
+    clippy::needless_borrows_for_generic_args,
+    clippy::never_loop,
+    clippy::redundant_field_names
+)]
 
 use std::collections::HashSet;
 use std::io::prelude::*;
@@ -6,6 +13,7 @@ use std::io::BufReader;
 use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
+use std::time::Duration;
 
 macro_rules! t {
     ($e:expr) => {
@@ -24,12 +32,16 @@ pub struct Server {
 
 enum Message {
     Read(String),
-    Write(String),
+    Write(Vec<u8>),
+    WritePartial(Vec<u8>),
+    Sleep(Duration),
 }
 
 fn run(listener: &TcpListener, rx: &Receiver<Message>) {
-    let mut socket = BufReader::new(listener.accept().unwrap().0);
+    // Every write ends a connection, the next message is for a new one.
+    let mut connection = None;
     for msg in rx.iter() {
+        let socket = connection.get_or_insert_with(|| BufReader::new(listener.accept().unwrap().0));
         match msg {
             Message::Read(ref expected) => {
                 let mut expected = &expected[..];
@@ -66,7 +78,7 @@ fn run(listener: &TcpListener, rx: &Receiver<Message>) {
                     let mut found = None;
                     for header in expected_headers.iter() {
                         if lines_match(header, &actual) {
-                            found = Some(header.clone());
+                            found = Some(*header);
                             break;
                         }
                     }
@@ -115,15 +127,21 @@ fn run(listener: &TcpListener, rx: &Receiver<Message>) {
                 }
             }
             Message::Write(ref to_write) => {
-                t!(socket.get_mut().write_all(to_write.as_bytes()));
-                return;
+                t!(socket.get_mut().write_all(to_write));
+                connection = None;
             }
+            Message::WritePartial(ref to_write) => {
+                t!(socket.get_mut().write_all(to_write));
+            }
+            Message::Sleep(duration) => thread::sleep(duration),
         }
     }
 
-    let mut dst = Vec::new();
-    t!(socket.read_to_end(&mut dst));
-    assert!(dst.len() == 0);
+    if let Some(mut socket) = connection {
+        let mut dst = Vec::new();
+        t!(socket.read_to_end(&mut dst));
+        assert!(dst.len() == 0);
+    }
 }
 
 fn lines_match(expected: &str, mut actual: &str) -> bool {
@@ -161,7 +179,20 @@ impl Server {
 
     pub fn send(&self, msg: &str) {
         let msg = msg.replace("$PORT", &self.addr.port().to_string());
-        self.msg(Message::Write(msg));
+        self.msg(Message::Write(msg.into_bytes()));
+    }
+
+    /// Sends data without closing the connection.
+    pub fn send_partial(&self, msg: &str) {
+        self.msg(Message::WritePartial(msg.as_bytes().to_vec()));
+    }
+
+    pub fn sleep(&self, duration: Duration) {
+        self.msg(Message::Sleep(duration));
+    }
+
+    pub fn send_bytes(&self, msg: &[u8]) {
+        self.msg(Message::Write(msg.to_vec()));
     }
 
     fn msg(&self, msg: Message) {